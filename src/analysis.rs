@@ -0,0 +1,275 @@
+use std::{fmt::{self, Display, Formatter}, str::FromStr};
+
+use crate::movetext::{Eval, MoveRecord};
+
+/// Evaluations are clamped to this many centipawns, and forced mates count as this much.
+pub const CENTIPAWN_CEILING: i32 = 1000;
+
+/// Lichess' assumed evaluation of the starting position, in centipawns.
+pub const INITIAL_CENTIPAWNS: i32 = 15;
+
+/// Lichess' mistake labels, by how much a move drops the mover's win percentage.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MoveQuality {
+    /// Win percentage dropped by at least 5 points.
+    Inaccuracy,
+    /// Win percentage dropped by at least 10 points.
+    Mistake,
+    /// Win percentage dropped by at least 15 points.
+    Blunder,
+}
+
+impl MoveQuality {
+    /// Classify a move from the mover's win percentage before and after it.
+    pub fn from_win_percent_drop(drop: f64) -> Option<Self> {
+        if drop >= 15.0 {
+            Some(Self::Blunder)
+        } else if drop >= 10.0 {
+            Some(Self::Mistake)
+        } else if drop >= 5.0 {
+            Some(Self::Inaccuracy)
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for MoveQuality {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "inaccuracy" => Ok(Self::Inaccuracy),
+            "mistake" => Ok(Self::Mistake),
+            "blunder" => Ok(Self::Blunder),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for MoveQuality {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Inaccuracy => write!(f, "Inaccuracy"),
+            Self::Mistake => write!(f, "Mistake"),
+            Self::Blunder => write!(f, "Blunder"),
+        }
+    }
+}
+
+/// An evaluation as White-relative centipawns, clamped to [`CENTIPAWN_CEILING`].
+pub fn clamped_centipawns(eval: &Eval) -> i32 {
+    match *eval {
+        Eval::Centipawns(cp) => cp.clamp(-CENTIPAWN_CEILING, CENTIPAWN_CEILING),
+        Eval::Mate(n) if n < 0 => -CENTIPAWN_CEILING,
+        Eval::Mate(_) => CENTIPAWN_CEILING,
+    }
+}
+
+/// White's win percentage (0-100) for a centipawn evaluation, using Lichess' logistic model.
+pub fn win_percent(centipawns: i32) -> f64 {
+    let cp = centipawns.clamp(-CENTIPAWN_CEILING, CENTIPAWN_CEILING) as f64;
+    50.0 + 50.0 * (2.0 / (1.0 + (-0.00368208 * cp).exp()) - 1.0)
+}
+
+/// Lichess' accuracy (0-100) of a single move, from the mover's win percentage before and after it.
+pub fn move_accuracy(win_percent_before: f64, win_percent_after: f64) -> f64 {
+    if win_percent_after >= win_percent_before {
+        return 100.0;
+    }
+    let drop = win_percent_before - win_percent_after;
+    let raw = 103.1668100711649 * (-0.04354415386753951 * drop).exp() - 3.166924740191411;
+    // Lichess adds one point to account for imperfect analysis.
+    (raw + 1.0).clamp(0.0, 100.0)
+}
+
+/// How good a single move was, judged from the evaluations before and after it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MoveJudgement {
+    /// 1-based half-move number.
+    pub ply: u32,
+    /// The mover's win percentage before the move.
+    pub win_percent_before: f64,
+    /// The mover's win percentage after the move.
+    pub win_percent_after: f64,
+    /// Centipawns lost by the move, from the mover's point of view; never negative.
+    pub centipawn_loss: u32,
+    pub accuracy: f64,
+    pub quality: Option<MoveQuality>,
+}
+
+/// Judge every move that has an evaluation both before and after it.
+///
+/// The position before the first move is taken to be [`INITIAL_CENTIPAWNS`].
+pub fn judge_moves(moves: &[MoveRecord]) -> Vec<MoveJudgement> {
+    let mut judgements = Vec::new();
+    let mut before = Some(INITIAL_CENTIPAWNS);
+    for (i, record) in moves.iter().enumerate() {
+        let after = record.eval.as_ref().map(clamped_centipawns);
+        if let (Some(before), Some(after)) = (before, after) {
+            let white_to_move = i % 2 == 0;
+            let (before_cp, after_cp) = if white_to_move {
+                (before, after)
+            } else {
+                (-before, -after)
+            };
+            let win_percent_before = win_percent(before_cp);
+            let win_percent_after = win_percent(after_cp);
+            judgements.push(MoveJudgement {
+                ply: i as u32 + 1,
+                win_percent_before,
+                win_percent_after,
+                centipawn_loss: (before_cp - after_cp).max(0) as u32,
+                accuracy: move_accuracy(win_percent_before, win_percent_after),
+                quality: MoveQuality::from_win_percent_drop(win_percent_before - win_percent_after),
+            });
+        }
+        before = after;
+    }
+    judgements
+}
+
+/// Per-player summary of move quality over a whole game.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerAnalysis {
+    /// Average centipawn loss.
+    pub acpl: f64,
+    /// Lichess game accuracy, 0-100.
+    pub accuracy: f64,
+    pub inaccuracies: u32,
+    pub mistakes: u32,
+    pub blunders: u32,
+}
+
+fn std_dev(xs: &[f64]) -> f64 {
+    let mean = xs.iter().sum::<f64>() / xs.len() as f64;
+    (xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / xs.len() as f64).sqrt()
+}
+
+/// Volatility weights for each move, as in Lichess' game accuracy: the standard deviation of
+/// White's win percentage over a sliding window ending at the move.
+fn volatility_weights(win_percents: &[f64], moves: usize) -> Vec<f64> {
+    let window_size = (moves / 10).clamp(2, 8);
+    let first = &win_percents[..window_size.min(win_percents.len())];
+    let mut windows: Vec<&[f64]> = vec![first; window_size - 2];
+    if win_percents.len() <= window_size {
+        windows.push(first);
+    } else {
+        windows.extend(win_percents.windows(window_size));
+    }
+    windows.iter().map(|w| std_dev(w).clamp(0.5, 12.0)).collect()
+}
+
+/// Summarize the move quality of both players, or `None` if the game has no evaluations.
+///
+/// # Returns
+///
+/// * `Some((white, black))`, where a player without judged moves gets an analysis of zeroes.
+pub fn analyze_moves(moves: &[MoveRecord]) -> Option<(PlayerAnalysis, PlayerAnalysis)> {
+    let judgements = judge_moves(moves);
+    if judgements.is_empty() {
+        return None;
+    }
+
+    // White-relative win percentages of every judged position, starting with the one before
+    // the first judged move, for the volatility weights.
+    let mut win_percents = vec![if judgements[0].ply % 2 == 1 {
+        judgements[0].win_percent_before
+    } else {
+        100.0 - judgements[0].win_percent_before
+    }];
+    win_percents.extend(judgements.iter().map(|j| {
+        if j.ply % 2 == 1 {
+            j.win_percent_after
+        } else {
+            100.0 - j.win_percent_after
+        }
+    }));
+    let weights = volatility_weights(&win_percents, judgements.len());
+
+    let player = |white: bool| {
+        let own: Vec<(&MoveJudgement, f64)> = judgements
+            .iter()
+            .zip(weights.iter().copied())
+            .filter(|(j, _)| (j.ply % 2 == 1) == white)
+            .collect();
+        if own.is_empty() {
+            return PlayerAnalysis {
+                acpl: 0.0,
+                accuracy: 0.0,
+                inaccuracies: 0,
+                mistakes: 0,
+                blunders: 0,
+            };
+        }
+        let n = own.len() as f64;
+        let weighted_mean = own.iter().map(|(j, w)| j.accuracy * w).sum::<f64>()
+            / own.iter().map(|(_, w)| w).sum::<f64>();
+        let harmonic_mean = n / own.iter().map(|(j, _)| 1.0 / j.accuracy.max(0.0001)).sum::<f64>();
+        let count = |quality: MoveQuality| {
+            own.iter().filter(|(j, _)| j.quality.as_ref() == Some(&quality)).count() as u32
+        };
+        PlayerAnalysis {
+            acpl: own.iter().map(|(j, _)| j.centipawn_loss as f64).sum::<f64>() / n,
+            accuracy: (weighted_mean + harmonic_mean) / 2.0,
+            inaccuracies: count(MoveQuality::Inaccuracy),
+            mistakes: count(MoveQuality::Mistake),
+            blunders: count(MoveQuality::Blunder),
+        }
+    };
+    Some((player(true), player(false)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movetext::parse_movetext;
+
+    #[test]
+    fn test_win_percent() {
+        assert_eq!(win_percent(0), 50.0);
+        assert!((win_percent(100) - 59.1).abs() < 0.1);
+        assert!((win_percent(-100) - 40.9).abs() < 0.1);
+        assert_eq!(win_percent(5000), win_percent(CENTIPAWN_CEILING));
+        assert_eq!(clamped_centipawns(&Eval::Mate(-2)), -CENTIPAWN_CEILING);
+    }
+
+    #[test]
+    fn test_move_accuracy() {
+        assert_eq!(move_accuracy(50.0, 60.0), 100.0);
+        assert!((move_accuracy(50.0, 50.0) - 100.0).abs() < 0.01);
+        assert!(move_accuracy(80.0, 20.0) < 10.0);
+    }
+
+    #[test]
+    fn test_judge_moves() {
+        let moves = parse_movetext(
+            "1. e4 { [%eval 0.2] } 1... e5 { [%eval 0.3] } 2. Qh5 { [%eval -0.3] } \
+             2... g6 { [%eval 5.0] } 3. Qxe5+ { [%eval 5.0] }",
+        );
+        let judgements = judge_moves(&moves);
+        assert_eq!(judgements.len(), 5);
+        assert_eq!(judgements[0].centipawn_loss, 0);
+        assert_eq!(judgements[1].centipawn_loss, 10);
+        assert_eq!(judgements[2].centipawn_loss, 60);
+        assert_eq!(judgements[2].quality, Some(MoveQuality::Inaccuracy));
+        assert_eq!(judgements[3].quality, Some(MoveQuality::Blunder));
+        assert_eq!(judgements[4].quality, None);
+    }
+
+    #[test]
+    fn test_analyze_moves() {
+        assert_eq!(analyze_moves(&parse_movetext("1. e4 e5")), None);
+
+        let moves = parse_movetext(
+            "1. e4 { [%eval 0.2] } 1... e5 { [%eval 0.3] } 2. Qh5 { [%eval -0.3] } \
+             2... g6 { [%eval 5.0] } 3. Qxe5+ { [%eval 5.0] }",
+        );
+        let (white, black) = analyze_moves(&moves).unwrap();
+        assert_eq!(white.acpl, 20.0);
+        assert_eq!(white.inaccuracies, 1);
+        assert_eq!(black.acpl, 270.0);
+        assert_eq!(black.blunders, 1);
+        assert!(white.accuracy > black.accuracy);
+        assert!((0.0..=100.0).contains(&black.accuracy));
+    }
+}
@@ -0,0 +1,118 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Result;
+
+/// Suffix of the temporary files that artifacts are written to before being renamed.
+pub const TEMP_SUFFIX: &str = ".tmp";
+
+/// The temporary path an artifact is written to before it gets its real name: a hidden
+/// file in the same directory, so the rename is atomic and directory listings looking
+/// for a file extension skip it.
+pub fn temp_path(path: &str) -> String {
+    let path = Path::new(path);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}{}", name, TEMP_SUFFIX))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Create the file at `path` so that it only ever appears complete.
+///
+/// `write` writes the contents to a temporary file in the same directory, which is
+/// synced and renamed to `path` if it succeeds, and removed if it fails.
+pub fn write_atomically<T>(
+    path: &str,
+    write: impl FnOnce(&mut fs::File) -> Result<T>,
+) -> Result<T> {
+    let temp = temp_path(path);
+    let result = fs::File::create(&temp)
+        .map_err(anyhow::Error::from)
+        .and_then(|mut file| {
+            let value = write(&mut file)?;
+            file.sync_all()?;
+            Ok(value)
+        })
+        .and_then(|value| {
+            fs::rename(&temp, path)?;
+            Ok(value)
+        });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Replace the file at `path` with `contents`, atomically.
+pub fn write_file_atomically(path: &str, contents: impl AsRef<[u8]>) -> Result<()> {
+    write_atomically(path, |file| Ok(file.write_all(contents.as_ref())?))
+}
+
+/// Delete the temporary files left under `root` by writes that were interrupted, e.g.
+/// by a crash. Must not run while anything is writing under `root`.
+///
+/// # Returns
+///
+/// * The number of files removed.
+pub fn remove_temp_files(root: &str) -> Result<usize> {
+    let root = Path::new(root);
+    if !root.is_dir() {
+        return Ok(0);
+    }
+    let mut removed = 0;
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if name.starts_with('.') && name.ends_with(TEMP_SUFFIX) {
+                fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = std::env::temp_dir().join(format!("chess_rs_atomic_{}", std::process::id()));
+        fs::create_dir_all(dir.join("2016/03")).unwrap();
+        let root = dir.to_str().unwrap();
+        let path = format!("{}/2016/03/games.parquet", root);
+        assert_eq!(
+            temp_path(&path),
+            format!("{}/2016/03/.games.parquet.tmp", root)
+        );
+
+        write_file_atomically(&path, "old").unwrap();
+        let failed = write_atomically(&path, |file| -> Result<()> {
+            file.write_all(b"partial")?;
+            Err(anyhow!("disk full"))
+        });
+        assert!(failed.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        assert!(!Path::new(&temp_path(&path)).exists());
+
+        fs::write(temp_path(&path), "stale").unwrap();
+        fs::write(format!("{}/2016/03/notes.tmp", root), "not ours").unwrap();
+        assert_eq!(remove_temp_files(root).unwrap(), 1);
+        assert!(!Path::new(&temp_path(&path)).exists());
+        assert!(Path::new(&path).exists());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
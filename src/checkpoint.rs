@@ -0,0 +1,201 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::atomic::write_file_atomically;
+use crate::manifest::ManifestSource;
+use crate::output::{OutputSettings, WrittenPart};
+use crate::players::PlayerIndex;
+
+/// Name of the checkpoint file in each month directory.
+pub const CHECKPOINT_FILE: &str = "checkpoint.json";
+
+/// Directory in each month directory holding the saved [`PlayerIndex`] segments.
+const PLAYERS_DIR: &str = "checkpoint_players";
+
+/// How far the processing of a month has got, saved to `checkpoint.json` after each step
+/// so that an interrupted run can pick up where it stopped.
+///
+/// Output is committed in whole batches of games: every file written up to
+/// [`offset`](Self::offset) in the PGN is listed in [`files`](Self::files), and a
+/// resumed run seeks past them and continues the part numbering from
+/// [`parts_written`](Self::parts_written), overwriting anything written after the last
+/// commit.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Checkpoint {
+    /// The archive, recorded once its download completed. An archive on disk that is
+    /// not recorded here may be half-written.
+    pub source: Option<ManifestSource>,
+    /// Whether the archive was completely decompressed to PGN.
+    pub decompressed: bool,
+    /// The output settings the files were written with; see [`for_outputs`](Self::for_outputs).
+    pub outputs: OutputSettings,
+    /// Byte offset in the PGN just past the last game whose output was committed.
+    pub offset: u64,
+    /// PGN game blocks read up to `offset`.
+    pub games_read: usize,
+    /// Games parsed up to `offset`.
+    pub games_parsed: usize,
+    /// Every output file committed so far.
+    pub files: Vec<WrittenPart>,
+    /// Files written so far per table and partition.
+    pub parts_written: BTreeMap<String, HashMap<String, usize>>,
+    /// Number of player index segments saved with [`save_players`](Self::save_players).
+    pub player_segments: usize,
+}
+
+impl Checkpoint {
+    /// Read the checkpoint of a month directory, or an empty one if it has none.
+    pub fn load(month_dir: &str) -> Result<Self> {
+        let path = Path::new(month_dir).join(CHECKPOINT_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json = fs::read_to_string(&path)?;
+        serde_json::from_str(&json).map_err(|e| anyhow!("Corrupt {}: {}", path.display(), e))
+    }
+
+    /// Save the checkpoint, replacing the previous one only once it is fully written.
+    pub fn save(&self, month_dir: &str) -> Result<()> {
+        let path = Path::new(month_dir).join(CHECKPOINT_FILE);
+        write_file_atomically(&path.to_string_lossy(), serde_json::to_string_pretty(self)?)
+    }
+
+    /// Delete the checkpoint of a month directory once the month is complete.
+    pub fn remove(month_dir: &str) -> Result<()> {
+        let path = Path::new(month_dir).join(CHECKPOINT_FILE);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        let players = Path::new(month_dir).join(PLAYERS_DIR);
+        if players.exists() {
+            fs::remove_dir_all(players)?;
+        }
+        Ok(())
+    }
+
+    /// Make sure the parsing progress applies to output written with the given settings,
+    /// discarding it if it was made with other settings.
+    ///
+    /// # Returns
+    ///
+    /// * `true` if there is progress to resume from.
+    pub fn for_outputs(&mut self, outputs: &OutputSettings) -> bool {
+        if self.outputs != *outputs {
+            *self = Self {
+                source: self.source.take(),
+                decompressed: self.decompressed,
+                outputs: *outputs,
+                ..Self::default()
+            };
+        }
+        self.offset > 0
+    }
+
+    /// Throw away all parsing progress, e.g. because the PGN is decompressed again, and
+    /// delete the output files recorded so far, so the month is parsed from the start.
+    pub fn restart_parsing(&mut self, month_dir: &str) -> Result<()> {
+        for part in &self.files {
+            match fs::remove_file(&part.path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        let players = Path::new(month_dir).join(PLAYERS_DIR);
+        if players.exists() {
+            fs::remove_dir_all(players)?;
+        }
+        *self = Self {
+            source: self.source.take(),
+            decompressed: self.decompressed,
+            outputs: self.outputs,
+            ..Self::default()
+        };
+        Ok(())
+    }
+
+    /// The part numbering to continue from for a table.
+    pub fn parts_written(&self, table: &str) -> HashMap<String, usize> {
+        self.parts_written.get(table).cloned().unwrap_or_default()
+    }
+
+    /// Save the players seen since the last segment, to be combined again with
+    /// [`load_players`](Self::load_players).
+    pub fn save_players(&mut self, month_dir: &str, index: &PlayerIndex) -> Result<()> {
+        let dir = Path::new(month_dir).join(PLAYERS_DIR);
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("players-{:03}.json", self.player_segments + 1));
+        write_file_atomically(&path.to_string_lossy(), serde_json::to_string(index)?)?;
+        self.player_segments += 1;
+        Ok(())
+    }
+
+    /// The players of every saved segment, merged.
+    pub fn load_players(&self, month_dir: &str) -> Result<PlayerIndex> {
+        let mut index = PlayerIndex::new();
+        for segment in 1..=self.player_segments {
+            let path = Path::new(month_dir)
+                .join(PLAYERS_DIR)
+                .join(format!("players-{:03}.json", segment));
+            index.merge(serde_json::from_str(&fs::read_to_string(path)?)?);
+        }
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::OutputLayout;
+
+    #[test]
+    fn test_save_load_and_remove() {
+        let dir = std::env::temp_dir().join(format!("chess_rs_checkpoint_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dir = dir.to_str().unwrap();
+        assert_eq!(Checkpoint::load(dir).unwrap(), Checkpoint::default());
+
+        let mut checkpoint = Checkpoint {
+            decompressed: true,
+            ..Checkpoint::default()
+        };
+        let flat = OutputSettings::default();
+        let hive = OutputSettings {
+            layout: OutputLayout::Hive,
+            ..flat
+        };
+        assert!(!checkpoint.for_outputs(&flat));
+        checkpoint.offset = 1234;
+        checkpoint.games_read = 10;
+        checkpoint.files.push(WrittenPart {
+            table: "games".to_string(),
+            path: "2016/03/2016-03__001.parquet".to_string(),
+            rows: 9,
+        });
+        checkpoint
+            .parts_written
+            .insert("games".to_string(), HashMap::from([(String::new(), 1)]));
+        checkpoint.save_players(dir, &PlayerIndex::new()).unwrap();
+        checkpoint.save(dir).unwrap();
+
+        let mut loaded = Checkpoint::load(dir).unwrap();
+        assert_eq!(loaded, checkpoint);
+        assert_eq!(loaded.parts_written("games")[""], 1);
+        assert!(loaded.load_players(dir).unwrap().into_players().is_empty());
+        assert!(loaded.for_outputs(&flat));
+
+        assert!(!loaded.for_outputs(&hive));
+        assert_eq!(loaded.offset, 0);
+        assert!(loaded.files.is_empty());
+        assert!(loaded.decompressed);
+
+        Checkpoint::remove(dir).unwrap();
+        assert_eq!(Checkpoint::load(dir).unwrap(), Checkpoint::default());
+        assert!(!Path::new(dir).join(PLAYERS_DIR).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,469 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{NaiveDate, NaiveTime};
+use polars::prelude::*;
+
+use crate::analysis::PlayerAnalysis;
+use crate::movetext::{Eval, MoveRecord};
+use crate::{BoardOutcome, ChessGame, GameType, TerminationType, TimeControl, Winner};
+
+/// Version of the output table schemas, recorded in each month's manifest. Bump it
+/// whenever a column is added, removed or changes type.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The columns of the games table and their types, in order.
+///
+/// Enums are stored as categoricals of their `Display` strings, Elo ratings as
+/// unsigned integers, and optional fields as nullable columns. The moves are
+/// stored as one list column per move attribute, with one element per ply, so
+/// each game stays a single row.
+pub fn game_schema() -> Schema {
+    let categorical = || DataType::Categorical(None);
+    let list = |inner: DataType| DataType::List(Box::new(inner));
+    Schema::from_iter([
+        Field::new("game_id", DataType::Utf8),
+        Field::new("rated", DataType::Boolean),
+        Field::new("url", DataType::Utf8),
+        Field::new("game_type", categorical()),
+        Field::new("time_control_base", DataType::UInt32),
+        Field::new("time_control_increment", DataType::UInt32),
+        Field::new("white_player_name", DataType::Utf8),
+        Field::new("white_player_elo", DataType::UInt32),
+        Field::new("black_player_name", DataType::Utf8),
+        Field::new("black_player_elo", DataType::UInt32),
+        Field::new("rating_diff", DataType::Int32),
+        Field::new("white_title", DataType::Utf8),
+        Field::new("black_title", DataType::Utf8),
+        Field::new("white_rating_change", DataType::Int32),
+        Field::new("black_rating_change", DataType::Int32),
+        Field::new("winner", categorical()),
+        Field::new("termination_type", categorical()),
+        Field::new("date", DataType::Date),
+        Field::new("time", DataType::Time),
+        Field::new("opening_name", DataType::Utf8),
+        Field::new("opening_eco", DataType::Utf8),
+        Field::new("ply_count", DataType::UInt32),
+        Field::new("final_fen", DataType::Utf8),
+        Field::new("board_outcome", categorical()),
+        Field::new("white_acpl", DataType::Float64),
+        Field::new("white_accuracy", DataType::Float64),
+        Field::new("white_inaccuracies", DataType::UInt32),
+        Field::new("white_mistakes", DataType::UInt32),
+        Field::new("white_blunders", DataType::UInt32),
+        Field::new("black_acpl", DataType::Float64),
+        Field::new("black_accuracy", DataType::Float64),
+        Field::new("black_inaccuracies", DataType::UInt32),
+        Field::new("black_mistakes", DataType::UInt32),
+        Field::new("black_blunders", DataType::UInt32),
+        Field::new("moves_san", list(DataType::Utf8)),
+        Field::new("moves_uci", list(DataType::Utf8)),
+        Field::new("clocks", list(DataType::Duration(TimeUnit::Milliseconds))),
+        Field::new("evals", list(DataType::Int32)),
+    ])
+}
+
+/// A list column with one list per game, built from each game's moves.
+fn move_list_column(
+    name: &str,
+    games: &[ChessGame],
+    inner: DataType,
+    f: impl Fn(&[MoveRecord]) -> Series,
+) -> PolarsResult<Series> {
+    let lists = games
+        .iter()
+        .map(|g| f(&g.moves).cast(&inner))
+        .collect::<PolarsResult<Vec<Series>>>()?;
+    let dtype = DataType::List(Box::new(inner));
+    if lists.is_empty() {
+        // Polars cannot infer the list type from an empty `Vec<Series>`.
+        return Ok(Series::new_empty(name, &dtype));
+    }
+    Series::new(name, lists).cast(&dtype)
+}
+
+/// The per-player analysis columns, for White or Black.
+fn analysis_columns(
+    games: &[ChessGame],
+    color: &str,
+    analysis: fn(&ChessGame) -> Option<&PlayerAnalysis>,
+) -> Vec<Series> {
+    let float = |field: &str, f: fn(&PlayerAnalysis) -> f64| {
+        let values: Vec<Option<f64>> = games.iter().map(|g| analysis(g).map(f)).collect();
+        Series::new(&format!("{}_{}", color, field), values)
+    };
+    let count = |field: &str, f: fn(&PlayerAnalysis) -> u32| {
+        let values: Vec<Option<u32>> = games.iter().map(|g| analysis(g).map(f)).collect();
+        Series::new(&format!("{}_{}", color, field), values)
+    };
+    vec![
+        float("acpl", |a| a.acpl),
+        float("accuracy", |a| a.accuracy),
+        count("inaccuracies", |a| a.inaccuracies),
+        count("mistakes", |a| a.mistakes),
+        count("blunders", |a| a.blunders),
+    ]
+}
+
+/// Convert games to a DataFrame with the [`game_schema`].
+pub fn games_to_dataframe(games: &[ChessGame]) -> PolarsResult<DataFrame> {
+    let utf8 = |name: &str, f: fn(&ChessGame) -> &str| {
+        Series::new(name, games.iter().map(f).collect::<Vec<&str>>())
+    };
+    let uint = |name: &str, f: fn(&ChessGame) -> u32| {
+        Series::new(name, games.iter().map(f).collect::<Vec<u32>>())
+    };
+    let optional_utf8 = |name: &str, f: fn(&ChessGame) -> Option<&str>| {
+        Series::new(name, games.iter().map(f).collect::<Vec<Option<&str>>>())
+    };
+    let int = |name: &str, f: fn(&ChessGame) -> Option<i32>| {
+        Series::new(name, games.iter().map(f).collect::<Vec<Option<i32>>>())
+    };
+    let categorical = |name: &str, f: fn(&ChessGame) -> Option<String>| {
+        Series::new(name, games.iter().map(f).collect::<Vec<Option<String>>>())
+            .cast(&DataType::Categorical(None))
+    };
+
+    let mut columns = vec![
+        utf8("game_id", |g| &g.game_id),
+        Series::new("rated", games.iter().map(|g| g.rated).collect::<Vec<bool>>()),
+        utf8("url", |g| &g.url),
+        categorical("game_type", |g| Some(g.game_type.to_string()))?,
+        uint("time_control_base", |g| g.time_control.base_seconds()),
+        uint("time_control_increment", |g| g.time_control.increment_seconds()),
+        utf8("white_player_name", |g| &g.white_player_name),
+        uint("white_player_elo", |g| g.white_player_elo),
+        utf8("black_player_name", |g| &g.black_player_name),
+        uint("black_player_elo", |g| g.black_player_elo),
+        Series::new("rating_diff", games.iter().map(|g| g.rating_diff).collect::<Vec<i32>>()),
+        optional_utf8("white_title", |g| g.white_title.as_deref()),
+        optional_utf8("black_title", |g| g.black_title.as_deref()),
+        int("white_rating_change", |g| g.white_rating_change),
+        int("black_rating_change", |g| g.black_rating_change),
+        categorical("winner", |g| g.winner.as_ref().map(|w| w.to_string()))?,
+        categorical("termination_type", |g| Some(g.termination_type.to_string()))?,
+        DateChunked::from_naive_date_options("date", games.iter().map(|g| g.date)).into_series(),
+        TimeChunked::from_naive_time_options("time", games.iter().map(|g| g.time)).into_series(),
+        utf8("opening_name", |g| &g.opening_name),
+        utf8("opening_eco", |g| &g.opening_eco),
+        uint("ply_count", |g| g.ply_count),
+        Series::new(
+            "final_fen",
+            games.iter().map(|g| g.final_fen.as_deref()).collect::<Vec<Option<&str>>>(),
+        ),
+        categorical("board_outcome", |g| g.board_outcome.as_ref().map(|o| o.to_string()))?,
+    ];
+    columns.extend(analysis_columns(games, "white", |g| g.white_analysis.as_ref()));
+    columns.extend(analysis_columns(games, "black", |g| g.black_analysis.as_ref()));
+    columns.push(move_list_column("moves_san", games, DataType::Utf8, |moves| {
+        Series::new("", moves.iter().map(|m| m.san.as_str()).collect::<Vec<&str>>())
+    })?);
+    columns.push(move_list_column("moves_uci", games, DataType::Utf8, |moves| {
+        Series::new("", moves.iter().map(|m| m.uci.as_deref()).collect::<Vec<Option<&str>>>())
+    })?);
+    columns.push(move_list_column(
+        "clocks",
+        games,
+        DataType::Duration(TimeUnit::Milliseconds),
+        |moves| {
+            let millis = moves.iter().map(|m| m.clock.map(|c| c.as_millis() as i64));
+            Series::new("", millis.collect::<Vec<Option<i64>>>())
+        },
+    )?);
+    columns.push(move_list_column("evals", games, DataType::Int32, |moves| {
+        let evals = moves.iter().map(|m| m.eval.as_ref().map(Eval::to_encoded));
+        Series::new("", evals.collect::<Vec<Option<i32>>>())
+    })?);
+    DataFrame::new(columns)
+}
+
+/// Whether a column of type `actual` can be read as the schema type `expected`.
+///
+/// Categorical columns may also be plain strings, as written by other tools.
+fn is_compatible(expected: &DataType, actual: &DataType) -> bool {
+    match expected {
+        DataType::Categorical(_) => matches!(actual, DataType::Categorical(_) | DataType::Utf8),
+        _ => expected == actual,
+    }
+}
+
+/// Check that a DataFrame has every column of the [`game_schema`] with a compatible type.
+/// Extra columns are allowed.
+pub fn validate_game_schema(df: &DataFrame) -> Result<()> {
+    let schema = df.schema();
+    for (name, expected) in game_schema().iter() {
+        let actual = schema
+            .get(name)
+            .ok_or_else(|| anyhow!("Missing column '{}' (expected type {})", name, expected))?;
+        if !is_compatible(expected, actual) {
+            return Err(anyhow!(
+                "Column '{}' has type {}, expected {}",
+                name,
+                actual,
+                expected
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The values of a string or categorical column.
+fn strings(df: &DataFrame, name: &str) -> Result<Vec<Option<String>>> {
+    let series = df.column(name)?.cast(&DataType::Utf8)?;
+    Ok(series.utf8()?.into_iter().map(|v| v.map(str::to_string)).collect())
+}
+
+fn u32s(df: &DataFrame, name: &str) -> Result<Vec<Option<u32>>> {
+    Ok(df.column(name)?.u32()?.into_iter().collect())
+}
+
+fn i32s(df: &DataFrame, name: &str) -> Result<Vec<Option<i32>>> {
+    Ok(df.column(name)?.i32()?.into_iter().collect())
+}
+
+fn f64s(df: &DataFrame, name: &str) -> Result<Vec<Option<f64>>> {
+    Ok(df.column(name)?.f64()?.into_iter().collect())
+}
+
+/// Unwrap a value of a non-nullable column.
+fn required<T>(value: Option<T>, name: &str, row: usize) -> Result<T> {
+    value.ok_or_else(|| anyhow!("Column '{}' is null at row {}", name, row))
+}
+
+/// Parse a value of a column holding an enum's `Display` string.
+fn parse_enum<T: FromStr>(value: &str, name: &str, row: usize) -> Result<T> {
+    T::from_str(value).map_err(|_| anyhow!("Invalid value '{}' in column '{}' at row {}", value, name, row))
+}
+
+/// Read one player's analysis columns; the analysis is present only if all of them are.
+fn player_analysis(df: &DataFrame, color: &str) -> Result<Vec<Option<PlayerAnalysis>>> {
+    let acpl = f64s(df, &format!("{}_acpl", color))?;
+    let accuracy = f64s(df, &format!("{}_accuracy", color))?;
+    let inaccuracies = u32s(df, &format!("{}_inaccuracies", color))?;
+    let mistakes = u32s(df, &format!("{}_mistakes", color))?;
+    let blunders = u32s(df, &format!("{}_blunders", color))?;
+    Ok((0..df.height())
+        .map(|i| {
+            Some(PlayerAnalysis {
+                acpl: acpl[i]?,
+                accuracy: accuracy[i]?,
+                inaccuracies: inaccuracies[i]?,
+                mistakes: mistakes[i]?,
+                blunders: blunders[i]?,
+            })
+        })
+        .collect())
+}
+
+/// The elements of each game's list in a list column, read with `f`.
+fn lists<T>(df: &DataFrame, name: &str, f: impl Fn(&Series) -> Result<Vec<T>>) -> Result<Vec<Vec<T>>> {
+    df.column(name)?
+        .list()?
+        .into_iter()
+        .map(|list| list.map_or_else(|| Ok(Vec::new()), |s| f(&s)))
+        .collect()
+}
+
+/// Rebuild each game's moves from the move list columns.
+fn moves(df: &DataFrame) -> Result<Vec<Vec<MoveRecord>>> {
+    let utf8 = |s: &Series| -> Result<Vec<Option<String>>> {
+        Ok(s.utf8()?.into_iter().map(|v| v.map(str::to_string)).collect())
+    };
+    let san = lists(df, "moves_san", utf8)?;
+    let uci = lists(df, "moves_uci", utf8)?;
+    let clocks = lists(df, "clocks", |s| Ok(s.cast(&DataType::Int64)?.i64()?.into_iter().collect()))?;
+    let evals = lists(df, "evals", |s| Ok(s.i32()?.into_iter().collect()))?;
+
+    let mut games = Vec::with_capacity(df.height());
+    for (i, san) in san.into_iter().enumerate() {
+        if [uci[i].len(), clocks[i].len(), evals[i].len()].iter().any(|&len| len != san.len()) {
+            return Err(anyhow!("Move list columns have different lengths at row {}", i));
+        }
+        let moves = san
+            .into_iter()
+            .enumerate()
+            .map(|(ply, san)| {
+                Ok(MoveRecord {
+                    san: required(san, "moves_san", i)?,
+                    uci: uci[i][ply].clone(),
+                    clock: clocks[i][ply].map(|ms| Duration::from_millis(ms as u64)),
+                    eval: evals[i][ply].map(Eval::from_encoded),
+                })
+            })
+            .collect::<Result<Vec<MoveRecord>>>()?;
+        games.push(moves);
+    }
+    Ok(games)
+}
+
+/// Convert a DataFrame with the [`game_schema`] back into games.
+///
+/// # Errors
+///
+/// Fails if a column is missing or has the wrong type, if a non-nullable column has
+/// a null, or if a categorical column holds a value that is not a valid enum variant.
+pub fn dataframe_to_games(df: &DataFrame) -> Result<Vec<ChessGame>> {
+    validate_game_schema(df)?;
+
+    let game_id = strings(df, "game_id")?;
+    let rated: Vec<Option<bool>> = df.column("rated")?.bool()?.into_iter().collect();
+    let url = strings(df, "url")?;
+    let game_type = strings(df, "game_type")?;
+    let base = u32s(df, "time_control_base")?;
+    let increment = u32s(df, "time_control_increment")?;
+    let white_player_name = strings(df, "white_player_name")?;
+    let white_player_elo = u32s(df, "white_player_elo")?;
+    let black_player_name = strings(df, "black_player_name")?;
+    let black_player_elo = u32s(df, "black_player_elo")?;
+    let rating_diff = i32s(df, "rating_diff")?;
+    let white_title = strings(df, "white_title")?;
+    let black_title = strings(df, "black_title")?;
+    let white_rating_change = i32s(df, "white_rating_change")?;
+    let black_rating_change = i32s(df, "black_rating_change")?;
+    let winner = strings(df, "winner")?;
+    let termination_type = strings(df, "termination_type")?;
+    let date: Vec<Option<NaiveDate>> = df.column("date")?.date()?.as_date_iter().collect();
+    let time: Vec<Option<NaiveTime>> = df.column("time")?.time()?.as_time_iter().collect();
+    let opening_name = strings(df, "opening_name")?;
+    let opening_eco = strings(df, "opening_eco")?;
+    let ply_count = u32s(df, "ply_count")?;
+    let final_fen = strings(df, "final_fen")?;
+    let board_outcome = strings(df, "board_outcome")?;
+    let white_analysis = player_analysis(df, "white")?;
+    let black_analysis = player_analysis(df, "black")?;
+    let mut moves = moves(df)?;
+
+    let mut games = Vec::with_capacity(df.height());
+    for i in 0..df.height() {
+        let game_type: GameType =
+            parse_enum(required(game_type[i].as_deref(), "game_type", i)?, "game_type", i)?;
+        let termination_type: TerminationType = parse_enum(
+            required(termination_type[i].as_deref(), "termination_type", i)?,
+            "termination_type",
+            i,
+        )?;
+        let winner: Option<Winner> = winner[i]
+            .as_deref()
+            .map(|w| parse_enum(w, "winner", i))
+            .transpose()?;
+        let board_outcome: Option<BoardOutcome> = board_outcome[i]
+            .as_deref()
+            .map(|o| parse_enum(o, "board_outcome", i))
+            .transpose()?;
+
+        games.push(
+            ChessGame::builder()
+                .rated(required(rated[i], "rated", i)?)
+                .url(required(url[i].clone(), "url", i)?)
+                .game_type(game_type)
+                .time_control(TimeControl::new(
+                    required(base[i], "time_control_base", i)?,
+                    required(increment[i], "time_control_increment", i)?,
+                ))
+                .white_player_name(required(white_player_name[i].clone(), "white_player_name", i)?)
+                .white_player_elo(required(white_player_elo[i], "white_player_elo", i)?)
+                .black_player_name(required(black_player_name[i].clone(), "black_player_name", i)?)
+                .black_player_elo(required(black_player_elo[i], "black_player_elo", i)?)
+                .rating_diff(required(rating_diff[i], "rating_diff", i)?)
+                .white_title(white_title[i].clone())
+                .black_title(black_title[i].clone())
+                .white_rating_change(white_rating_change[i])
+                .black_rating_change(black_rating_change[i])
+                .winner(winner)
+                .termination_type(termination_type)
+                .date(date[i])
+                .time(time[i])
+                .opening_name(required(opening_name[i].clone(), "opening_name", i)?)
+                .opening_eco(required(opening_eco[i].clone(), "opening_eco", i)?)
+                .game_id(required(game_id[i].clone(), "game_id", i)?)
+                .ply_count(required(ply_count[i], "ply_count", i)?)
+                .final_fen(final_fen[i].clone())
+                .board_outcome(board_outcome)
+                .white_analysis(white_analysis[i].clone())
+                .black_analysis(black_analysis[i].clone())
+                .moves(std::mem::take(&mut moves[i]))
+                .build()?,
+        );
+    }
+    Ok(games)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_game;
+
+    fn sample_game() -> ChessGame {
+        let mut game = test_game("g1");
+        game.winner = Some(Winner::Black);
+        game.termination_type = TerminationType::TimeForfeit;
+        game
+    }
+
+    #[test]
+    fn test_games_to_dataframe_matches_schema() {
+        let df = games_to_dataframe(&[sample_game()]).unwrap();
+        assert_eq!(df.schema(), game_schema());
+        assert_eq!(df.column("date").unwrap().null_count(), 1);
+        assert_eq!(df.column("white_acpl").unwrap().null_count(), 1);
+        assert_eq!(games_to_dataframe(&[]).unwrap().schema(), game_schema());
+    }
+
+    #[test]
+    fn test_dataframe_round_trip() {
+        let mut analysed = sample_game();
+        analysed.game_id = "g2".to_string();
+        analysed.winner = None;
+        analysed.termination_type = TerminationType::DrawByRule;
+        analysed.date = NaiveDate::from_ymd_opt(2016, 1, 31);
+        analysed.time = NaiveTime::from_hms_opt(23, 59, 59);
+        analysed.ply_count = 80;
+        analysed.final_fen = Some("8/8/8/4k3/8/8/8/4K3 w - - 0 41".to_string());
+        analysed.board_outcome = Some(BoardOutcome::InsufficientMaterial);
+        analysed.white_analysis = Some(PlayerAnalysis {
+            acpl: 23.5,
+            accuracy: 91.2,
+            inaccuracies: 1,
+            mistakes: 0,
+            blunders: 0,
+        });
+        analysed.black_analysis = analysed.white_analysis.clone();
+        analysed.moves = crate::movetext::parse_movetext(
+            "1. e4 { [%eval 0.2] [%clk 0:01:00.5] } 1... e5 { [%eval #-3] } 2. Qh5",
+        );
+        analysed.moves[0].uci = Some("e2e4".to_string());
+
+        let games = vec![sample_game(), analysed];
+        let df = games_to_dataframe(&games).unwrap();
+        assert_eq!(dataframe_to_games(&df).unwrap(), games);
+    }
+
+    #[test]
+    fn test_dataframe_to_games_accepts_plain_strings() {
+        let mut df = games_to_dataframe(&[sample_game()]).unwrap();
+        let game_type = df.column("game_type").unwrap().cast(&DataType::Utf8).unwrap();
+        df.with_column(game_type).unwrap();
+        assert_eq!(dataframe_to_games(&df).unwrap(), vec![sample_game()]);
+    }
+
+    #[test]
+    fn test_dataframe_to_games_errors() {
+        let df = games_to_dataframe(&[sample_game()]).unwrap();
+
+        let missing = df.drop("opening_eco").unwrap();
+        let err = dataframe_to_games(&missing).unwrap_err().to_string();
+        assert!(err.contains("Missing column 'opening_eco'"), "{}", err);
+
+        let mut mistyped = df.clone();
+        let elo = mistyped.column("white_player_elo").unwrap().cast(&DataType::Int64).unwrap();
+        mistyped.with_column(elo).unwrap();
+        let err = dataframe_to_games(&mistyped).unwrap_err().to_string();
+        assert!(err.contains("Column 'white_player_elo' has type i64, expected u32"), "{}", err);
+
+        let mut invalid = df;
+        invalid.with_column(Series::new("game_type", &["Hyperbullet"])).unwrap();
+        let err = dataframe_to_games(&invalid).unwrap_err().to_string();
+        assert!(err.contains("Invalid value 'Hyperbullet' in column 'game_type'"), "{}", err);
+    }
+}
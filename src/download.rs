@@ -0,0 +1,660 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use derive_builder::Builder;
+use futures::StreamExt;
+use reqwest::{Client, StatusCode};
+use sha2::{Digest, Sha256};
+use tokio::fs as async_fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Semaphore;
+
+use crate::manifest::hex;
+use crate::progress::StageProgress;
+
+/// Parse a `sha256sums.txt` checksum list, as published next to the Lichess archives,
+/// into a map from file name to hex-encoded SHA-256.
+///
+/// Each line is a checksum followed by a file name, in the format of `sha256sum`.
+pub fn parse_checksums(text: &str) -> HashMap<String, String> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let sha256 = fields.next()?;
+            let name = fields.next()?.trim_start_matches('*');
+            Some((name.to_string(), sha256.to_lowercase()))
+        })
+        .collect()
+}
+
+/// Fetch and parse a checksum list, e.g.
+/// `https://database.lichess.org/standard/sha256sums.txt`.
+pub async fn fetch_checksums(client: &Client, url: &str) -> Result<HashMap<String, String>> {
+    let response = client.get(url).send().await?;
+    if !response.status().is_success() {
+        return Err(DownloadError::status(url, &response).into());
+    }
+    Ok(parse_checksums(&response.text().await?))
+}
+
+/// The file name at the end of a URL, as listed in a checksum list.
+pub fn url_file_name(url: &str) -> &str {
+    url.rsplit('/').next().unwrap_or(url)
+}
+
+/// A completed download.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Downloaded {
+    pub bytes: u64,
+    /// Hex-encoded SHA-256 of the file, computed while downloading.
+    pub sha256: String,
+}
+
+/// A download failure that may go away when the download is tried again.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DownloadError {
+    /// The server answered with an unsuccessful status, and maybe said when to retry.
+    Status {
+        url: String,
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    },
+    /// The body was shorter or longer than the `Content-Length`.
+    Length {
+        url: String,
+        expected: u64,
+        received: u64,
+    },
+    /// The file did not match its published checksum.
+    Checksum {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl DownloadError {
+    fn status(url: &str, response: &reqwest::Response) -> Self {
+        Self::Status {
+            url: url.to_string(),
+            status: response.status(),
+            retry_after: response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| parse_retry_after(value.to_str().ok()?)),
+        }
+    }
+
+    /// Whether trying again may help: throttling, server errors and damaged files are
+    /// worth retrying, while e.g. a missing file is not.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Status { status, .. } => {
+                *status == StatusCode::TOO_MANY_REQUESTS
+                    || *status == StatusCode::REQUEST_TIMEOUT
+                    || status.is_server_error()
+            }
+            Self::Length { .. } | Self::Checksum { .. } => true,
+        }
+    }
+}
+
+impl Display for DownloadError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Status { url, status, .. } => {
+                write!(f, "Download of {} failed with status: {}", url, status)
+            }
+            Self::Length {
+                url,
+                expected,
+                received,
+            } => write!(
+                f,
+                "Downloaded {} bytes from {}, but Content-Length was {}",
+                received, url, expected
+            ),
+            Self::Checksum {
+                url,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Checksum mismatch for {}: expected {}, got {}",
+                url, expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+/// The delay asked for by a `Retry-After` header, given either in seconds or as an HTTP
+/// date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    Some(
+        (at.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Where a download in progress is kept until it is complete.
+pub fn part_path(output_path: &str) -> String {
+    format!("{}.part", output_path)
+}
+
+/// The first byte position of a `Content-Range: bytes start-end/total` header.
+fn content_range_start(response: &reqwest::Response) -> Option<u64> {
+    let range = response
+        .headers()
+        .get(reqwest::header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?;
+    range
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Hash the bytes already downloaded to a part file.
+async fn hash_part(path: &str, hasher: &mut Sha256) -> Result<()> {
+    let mut file = async_fs::File::open(path).await?;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        hasher.update(&buffer[..n]);
+    }
+}
+
+/// Download a file from a URL and save it to `output_path`.
+///
+/// The file is downloaded to a `.part` file next to `output_path`, which only gets its
+/// real name once it is complete. If a part file is left over from an interrupted
+/// download, only the rest is requested with a `Range` header; servers that ignore it
+/// send the whole file again, which replaces the part file.
+///
+/// The bytes downloaded so far, including any resumed from, are reported to `progress`.
+///
+/// # Errors
+///
+/// Fails on an unsuccessful status, and if the body is shorter or longer than the
+/// `Content-Length` the server announced. A short part file is kept to resume from.
+pub async fn download_file(
+    client: &Client,
+    url: &str,
+    output_path: &str,
+    progress: &mut StageProgress,
+) -> Result<Downloaded> {
+    let part = part_path(output_path);
+    let mut start = match async_fs::metadata(&part).await {
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    };
+    let mut request = client.get(url);
+    if start > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", start));
+    }
+    let mut response = request.send().await?;
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // The part file is as long as the file or longer, so it cannot be trusted.
+        async_fs::remove_file(&part).await?;
+        start = 0;
+        response = client.get(url).send().await?;
+    }
+    if !response.status().is_success() {
+        return Err(DownloadError::status(url, &response).into());
+    }
+
+    let mut hasher = Sha256::new();
+    let mut file = if response.status() == StatusCode::PARTIAL_CONTENT {
+        if content_range_start(&response) != Some(start) {
+            return Err(anyhow!("{} sent a different range than requested", url));
+        }
+        progress.resume_from(start);
+        hash_part(&part, &mut hasher).await?;
+        async_fs::OpenOptions::new()
+            .append(true)
+            .open(&part)
+            .await?
+    } else {
+        start = 0;
+        async_fs::File::create(&part).await?
+    };
+    let expected = response.content_length().map(|length| start + length);
+    progress.set_total_bytes(expected);
+
+    // Stream the response bytes to the file, hashing them on the way.
+    let mut stream = response.bytes_stream();
+    let mut bytes = start;
+    while let Some(chunk) = stream.next().await {
+        let data = chunk?;
+        hasher.update(&data);
+        bytes += data.len() as u64;
+        file.write_all(&data).await?;
+        progress.update(bytes, 0);
+    }
+    file.flush().await?;
+    if let Some(expected) = expected.filter(|&expected| expected != bytes) {
+        // A short part file is kept to resume from; a long one is damaged.
+        if bytes > expected {
+            async_fs::remove_file(&part).await?;
+        }
+        return Err(DownloadError::Length {
+            url: url.to_string(),
+            expected,
+            received: bytes,
+        }
+        .into());
+    }
+    async_fs::rename(&part, output_path).await?;
+    Ok(Downloaded {
+        bytes,
+        sha256: hex(&hasher.finalize()),
+    })
+}
+
+/// Limits and retry policy of a [`DownloadScheduler`].
+#[derive(Debug, Clone, Builder, PartialEq)]
+pub struct DownloadConfig {
+    /// Downloads that may run at the same time.
+    #[builder(default = "2")]
+    pub max_concurrent: usize,
+    /// Tries per download, including the first.
+    #[builder(default = "5")]
+    pub attempts: usize,
+    /// Delay before the first retry, doubled for each one after.
+    #[builder(default = "Duration::from_secs(2)")]
+    pub initial_backoff: Duration,
+    /// Longest delay between tries, including delays asked for by `Retry-After`.
+    #[builder(default = "Duration::from_secs(300)")]
+    pub max_backoff: Duration,
+}
+
+impl DownloadConfig {
+    pub fn builder() -> DownloadConfigBuilder {
+        DownloadConfigBuilder::default()
+    }
+
+    /// The delay before retry number `retry` (from 1): exponential, with random jitter
+    /// in its upper half so that throttled clients do not all come back at once.
+    pub fn backoff(&self, retry: usize) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1).min(31) as u32);
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff);
+        let jitter = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        backoff.mul_f64(0.5 + 0.5 * jitter)
+    }
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self::builder().build().expect("all fields have defaults")
+    }
+}
+
+/// Runs downloads with a limit on how many run at once, retrying transient failures
+/// with exponential backoff. Clones share the limit.
+#[derive(Debug, Clone)]
+pub struct DownloadScheduler {
+    client: Client,
+    config: DownloadConfig,
+    permits: Arc<Semaphore>,
+}
+
+impl DownloadScheduler {
+    pub fn new(config: DownloadConfig) -> Self {
+        Self {
+            client: Client::new(),
+            permits: Arc::new(Semaphore::new(config.max_concurrent.max(1))),
+            config,
+        }
+    }
+
+    /// Run `attempt` until it succeeds, it fails with an error that is not transient, or
+    /// the configured attempts run out. Network errors count as transient, as do
+    /// [`DownloadError`]s for which [`is_transient`](DownloadError::is_transient) holds.
+    /// Failed attempts are reported to `progress`, which each attempt may borrow in turn.
+    async fn retry<T, F, Fut>(
+        &self,
+        progress: &tokio::sync::Mutex<&mut StageProgress>,
+        mut attempt: F,
+    ) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let attempts = self.config.attempts.max(1);
+        for retry in 1.. {
+            let error = match attempt().await {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            let (transient, retry_after) = match error.downcast_ref::<DownloadError>() {
+                Some(e @ DownloadError::Status { retry_after, .. }) => {
+                    (e.is_transient(), *retry_after)
+                }
+                Some(e) => (e.is_transient(), None),
+                None => (error.is::<reqwest::Error>(), None),
+            };
+            if !transient || retry >= attempts {
+                return Err(error);
+            }
+            let delay = retry_after
+                .map(|delay| delay.min(self.config.max_backoff))
+                .unwrap_or_else(|| self.config.backoff(retry));
+            progress.lock().await.retrying(&error.to_string(), delay);
+            tokio::time::sleep(delay).await;
+        }
+        unreachable!("the loop only ends by returning")
+    }
+
+    /// Fetch and parse a checksum list, retrying transient failures, which are reported
+    /// to `progress`.
+    pub async fn fetch_checksums(
+        &self,
+        url: &str,
+        progress: &mut StageProgress,
+    ) -> Result<HashMap<String, String>> {
+        let progress = tokio::sync::Mutex::new(progress);
+        self.retry(&progress, || fetch_checksums(&self.client, url))
+            .await
+    }
+
+    /// Download a file once a download slot is free, and check it against its published
+    /// checksum. Transient failures, including a checksum mismatch, are retried after a
+    /// backoff; an interrupted download resumes where it stopped.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL of the file to download.
+    /// * `output_path` - The path where the file will be saved.
+    /// * `expected_sha256` - The published checksum, if there is one; without it only the
+    ///   length is checked.
+    /// * `progress` - Receives the bytes downloaded and the failed attempts.
+    ///
+    /// # Errors
+    ///
+    /// Fails if no attempt produced a complete file with the expected checksum, after
+    /// removing the bad file.
+    pub async fn download(
+        &self,
+        url: &str,
+        output_path: &str,
+        expected_sha256: Option<&str>,
+        progress: &mut StageProgress,
+    ) -> Result<Downloaded> {
+        let _permit = self.permits.acquire().await?;
+        let progress = tokio::sync::Mutex::new(progress);
+        let result = self
+            .retry(&progress, || async {
+                let mut progress = progress.lock().await;
+                let downloaded =
+                    download_file(&self.client, url, output_path, &mut progress).await?;
+                match expected_sha256 {
+                    Some(expected) if !expected.eq_ignore_ascii_case(&downloaded.sha256) => {
+                        Err(DownloadError::Checksum {
+                            url: url.to_string(),
+                            expected: expected.to_string(),
+                            actual: downloaded.sha256,
+                        }
+                        .into())
+                    }
+                    _ => Ok(downloaded),
+                }
+            })
+            .await;
+        if result.is_err() && Path::new(output_path).exists() {
+            async_fs::remove_file(output_path).await?;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::{JsonLinesProgress, Stage};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// A stand-in HTTP server that answers each connection with the next canned
+    /// response, then stops. Returns its base URL and the requests it received.
+    fn serve(responses: Vec<Vec<u8>>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = stream.read(&mut buffer).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..n]);
+                }
+                received
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(&request).into_owned());
+                let _ = stream.write_all(&response);
+            }
+        });
+        (url, requests)
+    }
+
+    /// An HTTP response with the given status line, extra headers and body.
+    fn response(status: &str, headers: &[&str], body: &[u8]) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 {}\r\nConnection: close\r\n", status);
+        for header in headers {
+            response.push_str(header);
+            response.push_str("\r\n");
+        }
+        response.push_str("\r\n");
+        let mut response = response.into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    fn ok(body: &[u8]) -> Vec<u8> {
+        let length = format!("Content-Length: {}", body.len());
+        response("200 OK", &[&length], body)
+    }
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("chess_rs_{}_{}", name, std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    const HELLO_WORLD_SHA256: &str =
+        "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn test_parse_checksums() {
+        let checksums = parse_checksums(&format!(
+            "{}  lichess_db_standard_rated_2016-03.pgn.zst\nABCD *other.pgn.zst\n\n",
+            HELLO_SHA256
+        ));
+        assert_eq!(checksums.len(), 2);
+        assert_eq!(
+            checksums["lichess_db_standard_rated_2016-03.pgn.zst"],
+            HELLO_SHA256
+        );
+        assert_eq!(checksums["other.pgn.zst"], "abcd");
+        assert_eq!(
+            url_file_name("https://database.lichess.org/standard/x.pgn.zst"),
+            "x.pgn.zst"
+        );
+    }
+
+    fn progress() -> StageProgress {
+        StageProgress::new(Arc::new(()), Stage::Download, 2016, 3)
+    }
+
+    /// A scheduler that retries without waiting.
+    fn scheduler(attempts: usize) -> DownloadScheduler {
+        DownloadScheduler::new(
+            DownloadConfig::builder()
+                .attempts(attempts)
+                .initial_backoff(Duration::ZERO)
+                .build()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_backoff_and_retry_after() {
+        let config = DownloadConfig::default();
+        for retry in 1..=3 {
+            let full = Duration::from_secs(2 << (retry - 1));
+            let backoff = config.backoff(retry);
+            assert!(backoff >= full / 2 && backoff <= full, "{:?}", backoff);
+        }
+        assert!(config.backoff(30) <= config.max_backoff);
+
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn test_download_retries_on_checksum_mismatch() {
+        let (url, requests) = serve(vec![ok(b"hellx"), ok(b"hello")]);
+        let path = temp_path("download_retry");
+        let downloaded = scheduler(2)
+            .download(&url, &path, Some(HELLO_SHA256), &mut progress())
+            .await
+            .unwrap();
+        assert_eq!(downloaded.bytes, 5);
+        assert_eq!(downloaded.sha256, HELLO_SHA256);
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_retries_throttling_and_server_errors() {
+        let (url, requests) = serve(vec![
+            response("429 Too Many Requests", &["Retry-After: 0"], b""),
+            response("503 Service Unavailable", &["Content-Length: 0"], b""),
+            ok(b"hello"),
+            response("404 Not Found", &["Content-Length: 0"], b""),
+        ]);
+        let path = temp_path("download_throttled");
+        let scheduler = scheduler(3);
+        let events = Arc::new(JsonLinesProgress::new(Vec::new()));
+        let mut stage = StageProgress::new(events.clone(), Stage::Download, 2016, 3);
+        scheduler
+            .download(&url, &path, None, &mut stage)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+        assert_eq!(requests.lock().unwrap().len(), 3);
+        drop(stage);
+        let events = Arc::try_unwrap(events).ok().unwrap().into_inner();
+        let events: Vec<serde_json::Value> = String::from_utf8(events)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let retries: Vec<u64> = events
+            .iter()
+            .filter(|event| !event["error"].is_null())
+            .map(|event| event["retries"].as_u64().unwrap())
+            .collect();
+        assert_eq!(retries, [1, 2]);
+        std::fs::remove_file(&path).unwrap();
+
+        // A missing file is not retried.
+        let error = scheduler
+            .download(&url, &path, None, &mut progress())
+            .await
+            .unwrap_err();
+        let error = error.downcast_ref::<DownloadError>().unwrap();
+        assert!(matches!(error, DownloadError::Status { status, .. } if *status == 404));
+        assert_eq!(requests.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_download_resumes_from_part_file() {
+        let resumed = response(
+            "206 Partial Content",
+            &["Content-Length: 6", "Content-Range: bytes 5-10/11"],
+            b" world",
+        );
+        let (url, requests) = serve(vec![resumed, ok(b"hello world")]);
+        let path = temp_path("download_resume");
+        let client = Client::new();
+
+        std::fs::write(part_path(&path), "hello").unwrap();
+        let downloaded = download_file(&client, &url, &path, &mut progress())
+            .await
+            .unwrap();
+        assert_eq!(downloaded.bytes, 11);
+        assert_eq!(downloaded.sha256, HELLO_WORLD_SHA256);
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world");
+        assert!(!Path::new(&part_path(&path)).exists());
+        assert!(requests.lock().unwrap()[0]
+            .to_lowercase()
+            .contains("range: bytes=5-"));
+
+        // A server that ignores the range sends everything, replacing the part file.
+        std::fs::write(part_path(&path), "junk").unwrap();
+        let downloaded = download_file(&client, &url, &path, &mut progress())
+            .await
+            .unwrap();
+        assert_eq!(downloaded.sha256, HELLO_WORLD_SHA256);
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_fails_on_mismatch_or_truncation() {
+        let truncated = response("200 OK", &["Content-Length: 10"], b"hello");
+        let (url, _) = serve(vec![ok(b"hellx"), truncated]);
+        let path = temp_path("download_fail");
+
+        let error = scheduler(1)
+            .download(&url, &path, Some(HELLO_SHA256), &mut progress())
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Checksum mismatch"));
+        assert!(!Path::new(&path).exists());
+
+        assert!(scheduler(1)
+            .download(&url, &path, None, &mut progress())
+            .await
+            .is_err());
+        assert!(!Path::new(&path).exists());
+        let _ = std::fs::remove_file(part_path(&path));
+    }
+}
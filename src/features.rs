@@ -0,0 +1,246 @@
+use polars::prelude::*;
+use shakmaty::{ByRole, CastlingSide, Color, Position};
+
+use crate::output::{TableRow, TableWriter};
+use crate::replay::{Ply, PlyVisitor};
+use crate::GameType;
+
+/// Game phase weight of each piece type, as used by most engines' tapered evaluation.
+/// A full set of pieces sums to [`MAX_PHASE`].
+const PHASE_WEIGHTS: ByRole<u8> = ByRole {
+    pawn: 0,
+    knight: 1,
+    bishop: 1,
+    rook: 2,
+    queen: 4,
+    king: 0,
+};
+
+/// The phase score of the starting position.
+pub const MAX_PHASE: u8 = 24;
+
+/// Conventional material value of each piece type, in pawns.
+const PIECE_VALUES: ByRole<u8> = ByRole {
+    pawn: 1,
+    knight: 3,
+    bishop: 3,
+    rook: 5,
+    queen: 9,
+    king: 0,
+};
+
+/// Piece counts for one side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PieceCounts {
+    pub pawns: u8,
+    pub knights: u8,
+    pub bishops: u8,
+    pub rooks: u8,
+    pub queens: u8,
+}
+
+impl PieceCounts {
+    fn from_material(material: &ByRole<u8>) -> Self {
+        Self {
+            pawns: material.pawn,
+            knights: material.knight,
+            bishops: material.bishop,
+            rooks: material.rook,
+            queens: material.queen,
+        }
+    }
+
+    /// Total material in pawns.
+    pub fn material(&self) -> u32 {
+        self.pawns as u32 * PIECE_VALUES.pawn as u32
+            + self.knights as u32 * PIECE_VALUES.knight as u32
+            + self.bishops as u32 * PIECE_VALUES.bishop as u32
+            + self.rooks as u32 * PIECE_VALUES.rook as u32
+            + self.queens as u32 * PIECE_VALUES.queen as u32
+    }
+
+    /// Contribution of these pieces to the game phase score.
+    pub fn phase(&self) -> u8 {
+        self.knights * PHASE_WEIGHTS.knight
+            + self.bishops * PHASE_WEIGHTS.bishop
+            + self.rooks * PHASE_WEIGHTS.rook
+            + self.queens * PHASE_WEIGHTS.queen
+    }
+}
+
+/// Positional features of the position before a given ply is played.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlyFeatures {
+    pub game_id: String,
+    /// Only used to partition the table; not a column.
+    pub game_type: GameType,
+    /// 1-based half-move number; the features describe the position this move was played from.
+    pub ply: u32,
+    pub white_to_move: bool,
+    pub white: PieceCounts,
+    pub black: PieceCounts,
+    /// Phase score from 0 (bare kings and pawns) to [`MAX_PHASE`] (all pieces on the board).
+    /// Promotions can push it above the maximum, so it is clamped.
+    pub phase: u8,
+    pub white_can_castle_kingside: bool,
+    pub white_can_castle_queenside: bool,
+    pub black_can_castle_kingside: bool,
+    pub black_can_castle_queenside: bool,
+    pub in_check: bool,
+}
+
+impl PlyFeatures {
+    /// Compute the features of the position a ply is played from.
+    pub fn from_ply(game_id: &str, game_type: &GameType, ply: &Ply<'_>) -> Self {
+        let position = ply.position;
+        let material = position.board().material();
+        let white = PieceCounts::from_material(&material.white);
+        let black = PieceCounts::from_material(&material.black);
+        let castles = position.castles();
+        Self {
+            game_id: game_id.to_string(),
+            game_type: game_type.clone(),
+            ply: ply.number,
+            white_to_move: position.turn() == Color::White,
+            white,
+            black,
+            phase: (white.phase() + black.phase()).min(MAX_PHASE),
+            white_can_castle_kingside: castles.has(Color::White, CastlingSide::KingSide),
+            white_can_castle_queenside: castles.has(Color::White, CastlingSide::QueenSide),
+            black_can_castle_kingside: castles.has(Color::Black, CastlingSide::KingSide),
+            black_can_castle_queenside: castles.has(Color::Black, CastlingSide::QueenSide),
+            in_check: position.is_check(),
+        }
+    }
+
+    /// White material minus Black material, in pawns.
+    pub fn material_balance(&self) -> i32 {
+        self.white.material() as i32 - self.black.material() as i32
+    }
+}
+
+/// A [`PlyVisitor`] that records [`PlyFeatures`] for every ply of a game.
+pub struct FeatureExtractor {
+    game_id: String,
+    game_type: GameType,
+    features: Vec<PlyFeatures>,
+}
+
+impl FeatureExtractor {
+    pub fn new(game_id: &str, game_type: &GameType) -> Self {
+        Self {
+            game_id: game_id.to_string(),
+            game_type: game_type.clone(),
+            features: Vec::new(),
+        }
+    }
+
+    pub fn into_features(self) -> Vec<PlyFeatures> {
+        self.features
+    }
+}
+
+impl PlyVisitor for FeatureExtractor {
+    fn visit_ply(&mut self, ply: &Ply<'_>) {
+        self.features
+            .push(PlyFeatures::from_ply(&self.game_id, &self.game_type, ply));
+    }
+}
+
+/// Build a long-format DataFrame with one row per `(game_id, ply)`.
+pub fn ply_features_to_dataframe(features: &[PlyFeatures]) -> PolarsResult<DataFrame> {
+    let counts = |name: &str, f: fn(&PlyFeatures) -> u8| {
+        Series::new(name, features.iter().map(|x| f(x) as u32).collect::<Vec<u32>>())
+    };
+    let flags = |name: &str, f: fn(&PlyFeatures) -> bool| {
+        Series::new(name, features.iter().map(f).collect::<Vec<bool>>())
+    };
+
+    DataFrame::new(vec![
+        Series::new("game_id", features.iter().map(|x| x.game_id.as_str()).collect::<Vec<&str>>()),
+        Series::new("ply", features.iter().map(|x| x.ply).collect::<Vec<u32>>()),
+        flags("white_to_move", |x| x.white_to_move),
+        Series::new("white_material", features.iter().map(|x| x.white.material()).collect::<Vec<u32>>()),
+        Series::new("black_material", features.iter().map(|x| x.black.material()).collect::<Vec<u32>>()),
+        Series::new("material_balance", features.iter().map(|x| x.material_balance()).collect::<Vec<i32>>()),
+        counts("white_pawns", |x| x.white.pawns),
+        counts("white_knights", |x| x.white.knights),
+        counts("white_bishops", |x| x.white.bishops),
+        counts("white_rooks", |x| x.white.rooks),
+        counts("white_queens", |x| x.white.queens),
+        counts("black_pawns", |x| x.black.pawns),
+        counts("black_knights", |x| x.black.knights),
+        counts("black_bishops", |x| x.black.bishops),
+        counts("black_rooks", |x| x.black.rooks),
+        counts("black_queens", |x| x.black.queens),
+        counts("phase", |x| x.phase),
+        flags("white_can_castle_kingside", |x| x.white_can_castle_kingside),
+        flags("white_can_castle_queenside", |x| x.white_can_castle_queenside),
+        flags("black_can_castle_kingside", |x| x.black_can_castle_kingside),
+        flags("black_can_castle_queenside", |x| x.black_can_castle_queenside),
+        flags("in_check", |x| x.in_check),
+    ])
+}
+
+impl TableRow for PlyFeatures {
+    const TABLE: &'static str = "ply_features";
+
+    fn game_type(&self) -> &GameType {
+        &self.game_type
+    }
+
+    fn to_dataframe(rows: &[Self]) -> PolarsResult<DataFrame> {
+        ply_features_to_dataframe(rows)
+    }
+}
+
+/// Writes the ply-features table.
+pub type FeatureWriter = TableWriter<PlyFeatures>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movetext::parse_movetext;
+    use crate::replay::replay;
+
+    /// The features of a blitz game, extracted while replaying its moves.
+    fn features(movetext: &str) -> Vec<PlyFeatures> {
+        let mut extractor = FeatureExtractor::new("g1", &GameType::Blitz);
+        replay(&parse_movetext(movetext), &mut [&mut extractor]).unwrap();
+        extractor.into_features()
+    }
+
+    #[test]
+    fn test_extract_ply_features() {
+        // An unsound queen sacrifice on f7.
+        let features = features("1. e4 e5 2. Qh5 Nc6 3. Qxf7+ Kxf7 4. Nf3");
+        assert_eq!(features.len(), 7);
+
+        let start = &features[0];
+        assert_eq!(start.ply, 1);
+        assert!(start.white_to_move);
+        assert_eq!(start.phase, MAX_PHASE);
+        assert_eq!(start.material_balance(), 0);
+        assert!(start.white_can_castle_kingside && start.black_can_castle_queenside);
+
+        // Before 3... Kxf7: Black is in check and has lost a pawn.
+        let check = &features[5];
+        assert!(check.in_check);
+        assert!(!check.white_to_move);
+        assert_eq!(check.material_balance(), 1);
+
+        // Before 4. Nf3: White is down a queen for a pawn, Black can no longer castle.
+        let last = &features[6];
+        assert_eq!(last.white.queens, 0);
+        assert_eq!(last.material_balance(), -8);
+        assert_eq!(last.phase, MAX_PHASE - 4);
+        assert!(!last.black_can_castle_kingside && !last.black_can_castle_queenside);
+    }
+
+    #[test]
+    fn test_ply_features_to_dataframe() {
+        let df = ply_features_to_dataframe(&features("1. e4 e5 2. Nf3")).unwrap();
+        assert_eq!(df.shape(), (3, 22));
+        assert_eq!(df.column("ply").unwrap().u32().unwrap().get(2), Some(3));
+    }
+}
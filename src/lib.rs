@@ -2,6 +2,33 @@ use std::{fmt::{self, Display, Formatter}, str::FromStr};
 use derive_builder::Builder;
 
 use chrono::{NaiveDate, NaiveTime};
+use serde::{Deserialize, Serialize};
+
+pub mod analysis;
+pub mod atomic;
+pub mod checkpoint;
+pub mod dataframe;
+pub mod download;
+pub mod features;
+pub mod manifest;
+pub mod movetext;
+pub mod output;
+pub mod parquet;
+pub mod pipeline;
+pub mod players;
+pub mod polyglot;
+pub mod positions;
+pub mod progress;
+pub mod puzzles;
+pub mod query;
+pub mod replay;
+pub mod source;
+pub mod sqlite;
+pub mod summary;
+pub mod time_usage;
+
+use analysis::PlayerAnalysis;
+use movetext::MoveRecord;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Winner {
@@ -33,6 +60,14 @@ impl Display for Winner {
 pub enum TerminationType {
     Normal,
     TimeForfeit,
+    /// A "Normal" decisive game that did not end in checkmate on the board.
+    Resignation,
+    /// A "Normal" game that ended in checkmate on the board.
+    Checkmate,
+    /// A "Normal" drawn game that ended by a rule of the game (see [`BoardOutcome`]).
+    DrawByRule,
+    /// The game has no result (`*`), so it was never finished.
+    Unterminated,
 }
 
 impl FromStr for TerminationType {
@@ -42,6 +77,10 @@ impl FromStr for TerminationType {
             "normal" => Ok(Self::Normal),
             "time forfeit" => Ok(Self::TimeForfeit),
             "time" => Ok(Self::TimeForfeit),
+            "resignation" => Ok(Self::Resignation),
+            "checkmate" => Ok(Self::Checkmate),
+            "draw by rule" => Ok(Self::DrawByRule),
+            "unterminated" => Ok(Self::Unterminated),
             _ => Err(()),
         }
     }
@@ -52,11 +91,58 @@ impl Display for TerminationType {
         match self {
             Self::Normal => write!(f, "Normal"),
             Self::TimeForfeit => write!(f, "Time forfeit"),
+            Self::Resignation => write!(f, "Resignation"),
+            Self::Checkmate => write!(f, "Checkmate"),
+            Self::DrawByRule => write!(f, "Draw by rule"),
+            Self::Unterminated => write!(f, "Unterminated"),
         }
     }
 }
 
+/// How a game ended on the board, as determined by replaying its moves.
 #[derive(Debug, Clone, PartialEq)]
+pub enum BoardOutcome {
+    Checkmate,
+    Stalemate,
+    InsufficientMaterial,
+    ThreefoldRepetition,
+    FiftyMoveRule,
+}
+
+impl BoardOutcome {
+    /// Whether the outcome is a draw.
+    pub fn is_draw(&self) -> bool {
+        !matches!(self, Self::Checkmate)
+    }
+}
+
+impl FromStr for BoardOutcome {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "checkmate" => Ok(Self::Checkmate),
+            "stalemate" => Ok(Self::Stalemate),
+            "insufficient material" => Ok(Self::InsufficientMaterial),
+            "threefold repetition" => Ok(Self::ThreefoldRepetition),
+            "fifty-move rule" => Ok(Self::FiftyMoveRule),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for BoardOutcome {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Checkmate => write!(f, "Checkmate"),
+            Self::Stalemate => write!(f, "Stalemate"),
+            Self::InsufficientMaterial => write!(f, "Insufficient material"),
+            Self::ThreefoldRepetition => write!(f, "Threefold repetition"),
+            Self::FiftyMoveRule => write!(f, "Fifty-move rule"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum GameType {
     Bullet,
     Blitz,
@@ -64,6 +150,11 @@ pub enum GameType {
     Classical,
 }
 
+impl GameType {
+    /// Every game type, from fastest to slowest.
+    pub const ALL: [GameType; 4] = [Self::Bullet, Self::Blitz, Self::Rapid, Self::Classical];
+}
+
 impl FromStr for GameType {
     type Err = ();
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -92,8 +183,18 @@ impl Display for GameType {
 pub struct TimeControl(u32, u32);
 
 impl TimeControl {
-    pub fn new(minutes: u32, increment: u32) -> Self {
-        Self(minutes, increment)
+    pub fn new(base_seconds: u32, increment_seconds: u32) -> Self {
+        Self(base_seconds, increment_seconds)
+    }
+
+    /// Base time in seconds, as written in the PGN `TimeControl` header (e.g. `180` in `180+2`).
+    pub fn base_seconds(&self) -> u32 {
+        self.0
+    }
+
+    /// Increment per move in seconds (e.g. `2` in `180+2`).
+    pub fn increment_seconds(&self) -> u32 {
+        self.1
     }
 }
 
@@ -110,9 +211,9 @@ impl FromStr for TimeControl {
         if parts.len() != 2 {
             return Err(());
         }
-        let minutes = parts[0].parse().map_err(|_| ())?;
-        let increment = parts[1].parse().map_err(|_| ())?;
-        Ok(Self(minutes, increment))
+        let base_seconds = parts[0].parse().map_err(|_| ())?;
+        let increment_seconds = parts[1].parse().map_err(|_| ())?;
+        Ok(Self(base_seconds, increment_seconds))
     }
 }
 
@@ -128,15 +229,47 @@ pub struct ChessGame {
     pub black_player_name: String,
     pub black_player_elo: u32,
     pub rating_diff: i32,
+    /// Titles from the `WhiteTitle`/`BlackTitle` headers, e.g. `GM` or `BOT`.
+    #[builder(default)]
+    pub white_title: Option<String>,
+    #[builder(default)]
+    pub black_title: Option<String>,
+    /// Rating changes from the `WhiteRatingDiff`/`BlackRatingDiff` headers, present for
+    /// rated games.
+    #[builder(default)]
+    pub white_rating_change: Option<i32>,
+    #[builder(default)]
+    pub black_rating_change: Option<i32>,
     /// Winner is "White" or "Black" when the result is decisive; if a draw then `None`.
+    /// Unfinished games have no winner either, see [`TerminationType::Unterminated`].
     pub winner: Option<Winner>,
-    /// Either `"Normal"` or `"Time forfeit"`.
+    /// `"Normal"`, `"Time forfeit"` or `"Unterminated"` from the headers (the latter also
+    /// for any game with a `*` result); a "Normal" game is refined to
+    /// resignation, checkmate or draw by rule when its moves could be replayed.
     pub termination_type: TerminationType,
     pub date: Option<NaiveDate>,
     pub time: Option<NaiveTime>,
     pub opening_name: String,
     pub opening_eco: String,
     pub game_id: String,
+    /// The moves of the game, in order, with any clock and eval annotations.
+    #[builder(default)]
+    pub moves: Vec<MoveRecord>,
+    /// Number of half-moves played.
+    #[builder(default)]
+    pub ply_count: u32,
+    /// FEN of the final position, if the moves could be replayed.
+    #[builder(default)]
+    pub final_fen: Option<String>,
+    /// How the game ended on the board, if it did (rather than by resignation, time or agreement).
+    #[builder(default)]
+    pub board_outcome: Option<BoardOutcome>,
+    /// White's accuracy and centipawn loss, if the game has eval annotations.
+    #[builder(default)]
+    pub white_analysis: Option<PlayerAnalysis>,
+    /// Black's accuracy and centipawn loss, if the game has eval annotations.
+    #[builder(default)]
+    pub black_analysis: Option<PlayerAnalysis>,
 }
 
 impl ChessGame {
@@ -145,6 +278,14 @@ impl ChessGame {
     }
 }
 
+/// The Lichess game id at the end of a game URL, e.g. `abcdefgh` for
+/// `https://lichess.org/abcdefgh`.
+pub fn lichess_game_id(url: &str) -> Option<&str> {
+    let id = url.strip_prefix("https://lichess.org/")?;
+    let id = id.split(['/', '#', '?']).next()?;
+    (!id.is_empty()).then_some(id)
+}
+
 /// Extract game type (eg "Bullet", "Blitz", "Rapid", "Classical") from the event string.
 pub fn extract_game_type_from_event_string(event: &str) -> GameType {
     let out: String;
@@ -177,10 +318,55 @@ pub fn extract_termination_type(termination: &str) -> TerminationType {
     match termination.to_lowercase().as_str() {
         "normal" => TerminationType::Normal,
         "time forfeit" => TerminationType::TimeForfeit,
+        "unterminated" => TerminationType::Unterminated,
         _ => panic!("Invalid termination type"),
     }
 }
 
+/// Split a "Normal" termination into resignation, checkmate or draw by rule, using the
+/// outcome found by replaying the game. Other terminations are returned unchanged, as
+/// are draws that did not end by rule (i.e. agreed draws).
+pub fn refine_termination_type(
+    termination: TerminationType,
+    winner: Option<&Winner>,
+    outcome: Option<&BoardOutcome>,
+) -> TerminationType {
+    if termination != TerminationType::Normal {
+        return termination;
+    }
+    match (winner, outcome) {
+        (Some(_), Some(BoardOutcome::Checkmate)) => TerminationType::Checkmate,
+        (Some(_), _) => TerminationType::Resignation,
+        (None, Some(outcome)) if outcome.is_draw() => TerminationType::DrawByRule,
+        (None, _) => TerminationType::Normal,
+    }
+}
+
+/// A rated 3+0 blitz game without moves between `alice` and `bob`, rated 1500 each and
+/// drawn, for tests to adjust to what they need.
+#[cfg(test)]
+pub(crate) fn test_game(game_id: &str) -> ChessGame {
+    ChessGame::builder()
+        .rated(true)
+        .url(format!("https://lichess.org/{}", game_id))
+        .game_type(GameType::Blitz)
+        .time_control(TimeControl::new(180, 0))
+        .white_player_name("alice".to_string())
+        .white_player_elo(1500)
+        .black_player_name("bob".to_string())
+        .black_player_elo(1500)
+        .rating_diff(0)
+        .winner(None)
+        .termination_type(TerminationType::Normal)
+        .date(None)
+        .time(None)
+        .opening_name("?".to_string())
+        .opening_eco("?".to_string())
+        .game_id(game_id.to_string())
+        .build()
+        .unwrap()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -205,6 +391,7 @@ mod tests {
         assert_eq!(TerminationType::from_str("normal"), Ok(TerminationType::Normal));
         assert_eq!(TerminationType::from_str("time forfeit"), Ok(TerminationType::TimeForfeit));
         assert_eq!(TerminationType::from_str("time"), Ok(TerminationType::TimeForfeit));
+        assert_eq!(TerminationType::from_str("draw by rule"), Ok(TerminationType::DrawByRule));
         assert_eq!(TerminationType::from_str("invalid"), Err(()));
     }
 
@@ -253,6 +440,52 @@ mod tests {
         assert_eq!(extract_termination_type("time forfeit"), TerminationType::TimeForfeit);
     }
 
+    #[test]
+    fn test_lichess_game_id() {
+        assert_eq!(lichess_game_id("https://lichess.org/abcdefgh"), Some("abcdefgh"));
+        assert_eq!(
+            lichess_game_id("https://lichess.org/abcdefgh/black#32"),
+            Some("abcdefgh")
+        );
+        assert_eq!(lichess_game_id("https://example.com/abcdefgh"), None);
+        assert_eq!(lichess_game_id("https://lichess.org/"), None);
+    }
+
+    #[test]
+    fn test_board_outcome_round_trip() {
+        for outcome in [
+            BoardOutcome::Checkmate,
+            BoardOutcome::Stalemate,
+            BoardOutcome::InsufficientMaterial,
+            BoardOutcome::ThreefoldRepetition,
+            BoardOutcome::FiftyMoveRule,
+        ] {
+            assert_eq!(BoardOutcome::from_str(&outcome.to_string()), Ok(outcome));
+        }
+    }
+
+    #[test]
+    fn test_refine_termination_type() {
+        let normal = || TerminationType::Normal;
+        assert_eq!(
+            refine_termination_type(normal(), Some(&Winner::White), Some(&BoardOutcome::Checkmate)),
+            TerminationType::Checkmate
+        );
+        assert_eq!(
+            refine_termination_type(normal(), Some(&Winner::Black), None),
+            TerminationType::Resignation
+        );
+        assert_eq!(
+            refine_termination_type(normal(), None, Some(&BoardOutcome::Stalemate)),
+            TerminationType::DrawByRule
+        );
+        assert_eq!(refine_termination_type(normal(), None, None), TerminationType::Normal);
+        assert_eq!(
+            refine_termination_type(TerminationType::TimeForfeit, Some(&Winner::White), None),
+            TerminationType::TimeForfeit
+        );
+    }
+
     #[test]
     fn test_time_control_from_str() {
         assert_eq!(TimeControl::from_str("5+5"), Ok(TimeControl(5, 5)));
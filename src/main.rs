@@ -1,39 +1,71 @@
 // src/main.rs
 
+use std::collections::HashMap;
 use std::fs;
-use std::io::{BufReader, BufWriter, Write};
+use std::io::{self, BufReader, BufWriter, Seek, SeekFrom, Write};
 use std::path::Path;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use anyhow::{anyhow, Result};
 use chrono::{NaiveDate, NaiveTime};
 use futures::stream::{FuturesUnordered, StreamExt};
-use polars::prelude::*;
-use rayon::prelude::*;
 use regex::Regex;
-use reqwest::Client;
-use tokio::fs as async_fs;
-use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
 use uuid::Uuid;
 
+use chess_rs::analysis::analyze_moves;
+use chess_rs::atomic::{remove_temp_files, write_atomically};
+use chess_rs::checkpoint::Checkpoint;
+use chess_rs::download::{url_file_name, DownloadConfig, DownloadScheduler};
+use chess_rs::features::{FeatureExtractor, FeatureWriter, PlyFeatures};
+use chess_rs::manifest::{read_manifest, Manifest, ManifestSource, MANIFEST_FILE};
+use chess_rs::movetext::parse_movetext;
+use chess_rs::output::{
+    write_month_table, ExtraOutputs, GameWriter, OutputFormat, OutputLayout, OutputSettings,
+    WrittenPart,
+};
+use chess_rs::parquet::{ParquetCodec, ParquetWriterConfig};
+use chess_rs::pipeline::{run_pipeline, PipelineConfig, PipelineStats};
+use chess_rs::players::{players_to_dataframe, rating_changes, PlayerIndex, RatingHistoryWriter};
+use chess_rs::positions::{PositionRecorder, PositionRow, PositionWriter};
+use chess_rs::progress::{
+    JsonLinesProgress, ProgressReader, ProgressSink, Stage, StageProgress, TerminalProgress,
+};
+use chess_rs::replay::PlyVisitor;
+use chess_rs::source::{DataSource, DatasetKind};
+use chess_rs::sqlite::SqliteWriter;
+use chess_rs::summary::replay_and_annotate;
 use chess_rs::{
     extract_game_type_from_event_string, extract_termination_type,
-    extract_winner_from_result_string, ChessGame, GameType, TerminationType, TimeControl, Winner,
+    extract_winner_from_result_string, lichess_game_id, refine_termination_type, ChessGame,
+    TerminationType, TimeControl,
 };
 
+/// A parsed game, with the rows of the per-ply tables recorded while its moves were replayed.
+pub struct ParsedGame {
+    pub game: ChessGame,
+    /// Empty unless the positions table is written, or if a move is illegal.
+    pub positions: Vec<PositionRow>,
+    /// Empty unless the ply-features table is written, or if a move is illegal.
+    pub features: Vec<PlyFeatures>,
+}
+
 /// Parse a single PGN game block into a [`ChessGame`] struct.
 ///
 /// # Arguments
 ///
 /// * `pgn_text` - A string slice containing one PGN game (its headers and optionally moves).
+/// * `extra` - The extra outputs; those made of per-ply rows are recorded on the same replay.
 ///
 /// # Returns
 ///
-/// * `Some(ChessGame)` if the required headers were found and parsed; otherwise, `None`.
-pub fn parse_pgn_game(pgn_text: &str) -> Option<ChessGame> {
+/// * `Some(ParsedGame)` if the required headers were found and parsed; otherwise, `None`.
+pub fn parse_pgn_game(pgn_text: &str, extra: &ExtraOutputs) -> Option<ParsedGame> {
     // Use a regex to extract header lines.
     let re = Regex::new(r#"^\[(\w+)\s+"([^"]+)"\]"#).unwrap();
     let mut headers = std::collections::HashMap::new();
+    let mut movetext = String::new();
 
     for line in pgn_text.lines() {
         let line = line.trim();
@@ -44,6 +76,9 @@ pub fn parse_pgn_game(pgn_text: &str) -> Option<ChessGame> {
             let key = caps.get(1)?.as_str();
             let value = caps.get(2)?.as_str();
             headers.insert(key, value);
+        } else {
+            movetext.push_str(line);
+            movetext.push('\n');
         }
     }
 
@@ -67,7 +102,55 @@ pub fn parse_pgn_game(pgn_text: &str) -> Option<ChessGame> {
 
     // Determine winner and termination type.
     let winner = extract_winner_from_result_string(result);
-    let termination_type = extract_termination_type(headers.get("Termination")?);
+    let termination = headers.get("Termination")?;
+    // Otherwise an unfinished game would pass for a draw.
+    let termination_type = if *result == "*" {
+        TerminationType::Unterminated
+    } else {
+        extract_termination_type(termination)
+    };
+
+    // The Lichess id keeps reruns idempotent; other sites' games get a random one.
+    let game_id = lichess_game_id(website)
+        .map_or_else(|| Uuid::new_v4().to_string(), str::to_string);
+
+    // Replay the moves to find out how the game ended on the board.
+    let mut moves = parse_movetext(&movetext);
+    let mut positions = extra
+        .positions
+        .then(|| PositionRecorder::new(&game_id, &game_type));
+    let mut features = extra
+        .features
+        .then(|| FeatureExtractor::new(&game_id, &game_type));
+    let summary = if moves.is_empty() {
+        None
+    } else {
+        let mut visitors: Vec<&mut dyn PlyVisitor> = Vec::new();
+        if let Some(recorder) = positions.as_mut() {
+            visitors.push(recorder);
+        }
+        if let Some(extractor) = features.as_mut() {
+            visitors.push(extractor);
+        }
+        replay_and_annotate(&mut moves, &mut visitors).ok()
+    };
+    // The rows stop at an illegal move, so none are kept.
+    let positions = positions
+        .filter(|_| summary.is_some())
+        .map_or_else(Vec::new, |recorder| recorder.rows);
+    let features = features
+        .filter(|_| summary.is_some())
+        .map_or_else(Vec::new, FeatureExtractor::into_features);
+    let termination_type = match &summary {
+        Some(summary) => {
+            refine_termination_type(termination_type, winner.as_ref(), summary.outcome.as_ref())
+        }
+        None => termination_type,
+    };
+    let (white_analysis, black_analysis) = match analyze_moves(&moves) {
+        Some((white, black)) => (Some(white), Some(black)),
+        None => (None, None),
+    };
 
     // Parse date and time if available.
     let date = if *utc_date_str == "????.??.??" {
@@ -82,7 +165,7 @@ pub fn parse_pgn_game(pgn_text: &str) -> Option<ChessGame> {
         NaiveTime::parse_from_str(utc_time_str, "%H:%M:%S").ok()
     };
 
-    Some(ChessGame::builder()
+    let game = ChessGame::builder()
         .rated(rated)
         .url(website.to_string())
         .game_type(game_type)
@@ -91,41 +174,31 @@ pub fn parse_pgn_game(pgn_text: &str) -> Option<ChessGame> {
         .white_player_elo(white_elo as u32)
         .black_player_name(black_player_name.to_string())
         .black_player_elo(black_elo as u32)
-        .rating_diff((white_elo - black_elo).abs() as i32)
+        .rating_diff((white_elo - black_elo).abs())
+        .white_title(headers.get("WhiteTitle").map(|t| t.to_string()))
+        .black_title(headers.get("BlackTitle").map(|t| t.to_string()))
+        .white_rating_change(headers.get("WhiteRatingDiff").and_then(|d| d.parse().ok()))
+        .black_rating_change(headers.get("BlackRatingDiff").and_then(|d| d.parse().ok()))
         .winner(winner)
         .termination_type(termination_type)
         .date(date)
         .time(time)
         .opening_name(opening.to_string())
         .opening_eco(eco.to_string())
-        .game_id(Uuid::new_v4().to_string())
+        .game_id(game_id)
+        .ply_count(moves.len() as u32)
+        .final_fen(summary.as_ref().map(|s| s.final_fen.clone()))
+        .board_outcome(summary.and_then(|s| s.outcome))
+        .white_analysis(white_analysis)
+        .black_analysis(black_analysis)
+        .moves(moves)
         .build()
-        .expect("Failed to build ChessGame"))
-}
-
-/// Download a file asynchronously from a URL and save it to `output_path`.
-///
-/// # Arguments
-///
-/// * `url` - The URL of the file to download.
-/// * `output_path` - The path where the file will be saved.
-pub async fn download_file(url: &str, output_path: &str) -> Result<()> {
-    let client = Client::new();
-    let response = client.get(url).send().await?;
-    if !response.status().is_success() {
-        return Err(anyhow!(
-            "Download failed with status: {}",
-            response.status()
-        ));
-    }
-    // Stream the response bytes and write them to file.
-    let mut stream = response.bytes_stream();
-    let mut file = async_fs::File::create(output_path).await?;
-    while let Some(chunk) = stream.next().await {
-        let data = chunk?;
-        file.write_all(&data).await?;
-    }
-    Ok(())
+        .expect("Failed to build ChessGame");
+    Some(ParsedGame {
+        game,
+        positions,
+        features,
+    })
 }
 
 /// Decompress a Zstandard-compressed file.
@@ -133,239 +206,639 @@ pub async fn download_file(url: &str, output_path: &str) -> Result<()> {
 /// # Arguments
 ///
 /// * `input_path` - The path to the compressed (.zst) file.
-/// * `output_path` - The path where the decompressed file is written.
-pub fn decompress_zst_file(input_path: &str, output_path: &str) -> Result<()> {
+/// * `output_path` - The path where the decompressed file is written, once complete.
+/// * `progress` - Receives the compressed bytes read so far.
+pub fn decompress_zst_file(
+    input_path: &str,
+    output_path: &str,
+    progress: &mut StageProgress,
+) -> Result<()> {
     let input_file = fs::File::open(input_path)?;
-    let mut reader = BufReader::new(input_file);
-    let output_file = fs::File::create(output_path)?;
-    let mut writer = BufWriter::new(output_file);
-    zstd::stream::copy_decode(&mut reader, &mut writer)?;
-    writer.flush()?;
-    Ok(())
+    progress.set_total_bytes(Some(input_file.metadata()?.len()));
+    let mut reader = BufReader::new(ProgressReader::new(input_file, progress));
+    write_atomically(output_path, |file| {
+        let mut writer = BufWriter::new(file);
+        zstd::stream::copy_decode(&mut reader, &mut writer)?;
+        writer.flush()?;
+        Ok(())
+    })
 }
 
-/// Parse a PGN file into a vector of [`ChessGame`] objects.
-///
-/// This function reads the entire PGN file into memory, splits the text on occurrences
-/// of `"[Event "` (re-adding the tag marker), and then uses Rayon to parse each game in parallel.
-///
-/// # Arguments
-///
-/// * `pgn_path` - The path to the PGN file.
-///
-/// # Returns
-///
-/// * A vector of parsed `ChessGame` objects.
-pub fn parse_pgn_file(pgn_path: &str) -> Result<Vec<ChessGame>> {
-    let content = fs::read_to_string(pgn_path)?;
-    // Split the file by occurrences of "[Event " and re-add the missing "[Event " to each block.
-    let games: Vec<String> = content
-        .split("[Event ")
-        .skip(1)
-        .map(|s| format!("[Event {}", s))
-        .collect();
-    // Process games in parallel.
-    let parsed_games: Vec<ChessGame> = games
-        .par_iter()
-        .filter_map(|game_text| parse_pgn_game(game_text))
-        .collect();
-    Ok(parsed_games)
-}
-
-// /// Write a slice of [`ChessGame`] objects to a Parquet file using Polars.
-// ///
-// /// # Arguments
-// ///
-// /// * `games` - A slice of `ChessGame` objects.
-// /// * `output_path` - The path for the output Parquet file.
-// pub fn write_games_to_parquet(games: &[ChessGame], output_path: &str) -> Result<()> {
-//     // Create vectors for each column.
-//     let mut rated_vec = Vec::with_capacity(games.len());
-//     let mut url_vec = Vec::with_capacity(games.len());
-//     let mut game_type_vec = Vec::with_capacity(games.len());
-//     let mut white_player_name_vec = Vec::with_capacity(games.len());
-//     let mut white_player_elo_vec = Vec::with_capacity(games.len());
-//     let mut black_player_name_vec = Vec::with_capacity(games.len());
-//     let mut black_player_elo_vec = Vec::with_capacity(games.len());
-//     let mut rating_diff_vec = Vec::with_capacity(games.len());
-//     let mut winner_vec = Vec::with_capacity(games.len());
-//     let mut termination_type_vec = Vec::with_capacity(games.len());
-//     let mut date_vec = Vec::with_capacity(games.len());
-//     let mut time_vec = Vec::with_capacity(games.len());
-//     let mut opening_name_vec = Vec::with_capacity(games.len());
-//     let mut opening_eco_vec = Vec::with_capacity(games.len());
-//     let mut game_id_vec = Vec::with_capacity(games.len());
-
-//     for game in games {
-//         rated_vec.push(game.rated);
-//         url_vec.push(game.url.clone());
-//         game_type_vec.push(game.game_type.clone());
-//         white_player_name_vec.push(game.white_player_name.clone());
-//         white_player_elo_vec.push(game.white_player_elo);
-//         black_player_name_vec.push(game.black_player_name.clone());
-//         black_player_elo_vec.push(game.black_player_elo);
-//         rating_diff_vec.push(game.rating_diff);
-//         winner_vec.push(game.winner.clone());
-//         termination_type_vec.push(game.termination_type.clone());
-//         // For simplicity, dates and times are stored as strings.
-//         date_vec.push(game.date.map(|d| d.format("%Y-%m-%d").to_string()));
-//         time_vec.push(game.time.map(|t| t.format("%H:%M:%S").to_string()));
-//         opening_name_vec.push(game.opening_name.clone());
-//         opening_eco_vec.push(game.opening_eco.clone());
-//         game_id_vec.push(game.game_id.clone());
-//     }
-
-//     // Build Series.
-//     let s_rated = Series::new("rated", rated_vec);
-//     let s_url = Series::new("url", url_vec);
-//     let s_game_type = Series::new("game_type", game_type_vec);
-//     let s_white_player_name = Series::new("white_player_name", white_player_name_vec);
-//     let s_white_player_elo = Series::new("white_player_elo", white_player_elo_vec);
-//     let s_black_player_name = Series::new("black_player_name", black_player_name_vec);
-//     let s_black_player_elo = Series::new("black_player_elo", black_player_elo_vec);
-//     let s_rating_diff = Series::new("rating_diff", rating_diff_vec);
-//     let s_winner = Series::new("winner", winner_vec);
-//     let s_termination_type = Series::new("termination_type", termination_type_vec);
-//     let s_date = Series::new("date", date_vec);
-//     let s_time = Series::new("time", time_vec);
-//     let s_opening_name = Series::new("opening_name", opening_name_vec);
-//     let s_opening_eco = Series::new("opening_eco", opening_eco_vec);
-//     let s_game_id = Series::new("game_id", game_id_vec);
-
-//     // Create the DataFrame.
-//     let mut df = DataFrame::new(vec![
-//         s_rated,
-//         s_url,
-//         s_game_type,
-//         s_white_player_name,
-//         s_white_player_elo,
-//         s_black_player_name,
-//         s_black_player_elo,
-//         s_rating_diff,
-//         s_winner,
-//         s_termination_type,
-//         s_date,
-//         s_time,
-//         s_opening_name,
-//         s_opening_eco,
-//         s_game_id,
-//     ])?;
-
-//     // Write the DataFrame to a Parquet file.
-//     let file = fs::File::create(output_path)?;
-//     ParquetWriter::new(file).finish(&mut df)?;
-//     Ok(())
-// }
-
 /// Ensure that the folder structure for a given year and month exists.
 ///
 /// # Arguments
 ///
+/// * `root` - The output root, e.g. `lichess_data`.
 /// * `year` - The year.
 /// * `month` - The month (1–12).
-pub fn ensure_folder_structure(year: i32, month: i32) -> Result<()> {
-    let folder_path = format!("lichess_data/{}/{}", year, format!("{:02}", month));
+pub fn ensure_folder_structure(root: &str, year: i32, month: i32) -> Result<()> {
+    let folder_path = format!("{}/{}/{:02}", root, year, month);
     fs::create_dir_all(&folder_path)?;
     Ok(())
 }
 
-/// Construct the URL for a given year and month.
+/// Where the archives come from and what is written for them, the same for every month.
+#[derive(Debug, Clone)]
+pub struct RunSettings {
+    pub source: DataSource,
+    /// The output root, e.g. `lichess_data`.
+    pub root: String,
+    pub outputs: OutputSettings,
+    /// Hash every output file of a month before skipping it, instead of trusting files
+    /// whose size and modification time match its manifest.
+    pub verify_checksums: bool,
+}
+
+/// The SQLite database of a month, e.g. `lichess_data/2016/03/2016-03.sqlite`.
+fn sqlite_path(root: &str, year: i32, month: i32) -> String {
+    format!("{0}/{1}/{2:02}/{1}-{2:02}.sqlite", root, year, month)
+}
+
+/// The writers of every output of a month.
+struct MonthWriters {
+    games: GameWriter,
+    positions: Option<PositionWriter>,
+    features: Option<FeatureWriter>,
+    ratings: Option<RatingHistoryWriter>,
+    /// Players seen since the last checkpoint.
+    players: PlayerIndex,
+    sqlite: Option<SqliteWriter>,
+}
+
+impl MonthWriters {
+    /// Open the writers, continuing the part numbering of the checkpoint.
+    ///
+    /// The games table is only written at checkpoints, so its files hold about
+    /// `games_per_file` games each (per game type, for the Hive layout).
+    fn new(
+        year: i32,
+        month: i32,
+        settings: &RunSettings,
+        config: &PipelineConfig,
+        checkpoint: &Checkpoint,
+    ) -> Result<Self> {
+        let root = &settings.root;
+        let OutputSettings {
+            layout,
+            format,
+            extra,
+        } = settings.outputs;
+        let sqlite = if extra.sqlite {
+            Some(SqliteWriter::open(&sqlite_path(root, year, month), 10_000)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            games: GameWriter::new(root, year, month, layout, format, usize::MAX)
+                .with_parts_written(checkpoint.parts_written("games")),
+            positions: extra.positions.then(|| {
+                PositionWriter::new(root, year, month, layout, format, config.positions_per_file)
+                    .with_parts_written(checkpoint.parts_written("positions"))
+            }),
+            features: extra.features.then(|| {
+                FeatureWriter::new(
+                    root,
+                    year,
+                    month,
+                    layout,
+                    format,
+                    config.ply_features_per_file,
+                )
+                .with_parts_written(checkpoint.parts_written("ply_features"))
+            }),
+            ratings: extra.players.then(|| {
+                RatingHistoryWriter::new(
+                    root,
+                    year,
+                    month,
+                    layout,
+                    format,
+                    config.rating_changes_per_file,
+                )
+                .with_parts_written(checkpoint.parts_written("rating_history"))
+            }),
+            players: PlayerIndex::new(),
+            sqlite,
+        })
+    }
+
+    fn push(&mut self, parsed: ParsedGame, written: &mut Vec<WrittenPart>) -> Result<()> {
+        let ParsedGame {
+            game,
+            positions,
+            features,
+        } = parsed;
+        if let Some(sqlite) = self.sqlite.as_mut() {
+            sqlite.push(&game)?;
+        }
+        if let Some(writer) = self.positions.as_mut() {
+            for row in positions {
+                written.extend(writer.push(row)?);
+            }
+        }
+        if let Some(writer) = self.features.as_mut() {
+            for row in features {
+                written.extend(writer.push(row)?);
+            }
+        }
+        if let Some(ratings) = self.ratings.as_mut() {
+            self.players.add_game(&game);
+            for change in rating_changes(&game) {
+                written.extend(ratings.push(change)?);
+            }
+        }
+        written.extend(self.games.push(game)?);
+        Ok(())
+    }
+
+    /// Write out everything buffered and record it, with the point in the PGN it covers,
+    /// in the checkpoint.
+    fn commit(&mut self, checkpoint: &mut Checkpoint, month_dir: &str) -> Result<()> {
+        checkpoint.files.extend(self.games.flush_all()?);
+        checkpoint
+            .parts_written
+            .insert("games".to_string(), self.games.parts_written().clone());
+        if let Some(positions) = self.positions.as_mut() {
+            checkpoint.files.extend(positions.flush_all()?);
+            checkpoint
+                .parts_written
+                .insert("positions".to_string(), positions.parts_written().clone());
+        }
+        if let Some(features) = self.features.as_mut() {
+            checkpoint.files.extend(features.flush_all()?);
+            checkpoint
+                .parts_written
+                .insert("ply_features".to_string(), features.parts_written().clone());
+        }
+        if let Some(ratings) = self.ratings.as_mut() {
+            checkpoint.files.extend(ratings.flush_all()?);
+            checkpoint
+                .parts_written
+                .insert("rating_history".to_string(), ratings.parts_written().clone());
+            checkpoint.save_players(month_dir, &std::mem::take(&mut self.players))?;
+        }
+        if let Some(sqlite) = self.sqlite.as_mut() {
+            sqlite.commit()?;
+        }
+        checkpoint.save(month_dir)
+    }
+}
+
+/// Process the entire flow for a given year and month:
+/// 1. Ensure the folder exists.
+/// 2. Download the compressed file and verify its checksum (unless a completed download is
+///    recorded).
+/// 3. Decompress the file (unless a completed decompression is recorded).
+/// 4. Parse the PGN into [`ChessGame`] objects in bounded batches.
+/// 5. Save each batch as it is parsed, in files of 100,000 games in the given layout and format.
+/// 6. Write `manifest.json` to the month directory, listing the counts and files.
+///
+/// Progress is recorded in a [`Checkpoint`] after every step and every file of games, so
+/// a rerun after a crash resumes from the last committed game instead of starting over,
+/// and a month with a valid manifest written with the same output settings is skipped.
 ///
 /// # Arguments
 ///
 /// * `year` - The year.
-/// * `month` - The month (1–12).
-///
-/// # Returns
-///
-/// * The constructed URL as a `String`.
-pub fn construct_url(year: i32, month: i32) -> String {
-    format!(
-        "https://database.lichess.org/standard/lichess_db_standard_rated_{}-{:02}.pgn.zst",
-        year, month
-    )
+/// * `month` - The month.
+/// * `settings` - The data source and the outputs to write.
+/// * `downloads` - The scheduler shared by every month's download.
+/// * `parsing` - Permits for decompressing and parsing, shared by every month.
+/// * `progress` - Receives the progress of each stage.
+pub async fn process_year_month(
+    year: i32,
+    month: i32,
+    settings: Arc<RunSettings>,
+    downloads: DownloadScheduler,
+    parsing: Arc<Semaphore>,
+    progress: Arc<dyn ProgressSink>,
+) -> Result<()> {
+    let root = settings.root.as_str();
+    ensure_folder_structure(root, year, month)?;
+    let work_dir = format!("{}/{}/{:02}", root, year, month);
+    let url = settings.source.url(year, month);
+
+    if let Ok(manifest) = read_manifest(&work_dir) {
+        let verified = if settings.verify_checksums {
+            manifest.verify_checksums(root)
+        } else {
+            manifest.verify(root)
+        };
+        if manifest.outputs == settings.outputs && verified.is_ok() {
+            progress.message(&format!(
+                "Already processed {}/{}; delete {}/{} to process it again.",
+                year, month, work_dir, MANIFEST_FILE
+            ));
+            return Ok(());
+        }
+    }
+    let mut checkpoint = Checkpoint::load(&work_dir)?;
+
+    let compressed_path = format!("{}/{}-{:02}.pgn.zst", work_dir, year, month);
+
+    // A file on disk is only trusted if the checkpoint says it was completed, since an
+    // interrupted download or decompression leaves a truncated one behind.
+    if checkpoint.source.is_none() || !Path::new(&compressed_path).exists() {
+        let mut stage = StageProgress::new(progress.clone(), Stage::Download, year, month);
+        let checksums = match settings.source.checksums_url() {
+            Some(checksums_url) => downloads.fetch_checksums(&checksums_url, &mut stage).await?,
+            None => HashMap::new(),
+        };
+        let expected = checksums.get(url_file_name(&url));
+        if expected.is_none() {
+            progress.message(&format!(
+                "No published checksum for {}; only checking its length.",
+                url
+            ));
+        }
+        let downloaded = downloads
+            .download(&url, &compressed_path, expected.map(String::as_str), &mut stage)
+            .await?;
+        stage.finish(downloaded.bytes, 0);
+        checkpoint.source = Some(ManifestSource {
+            url: url.clone(),
+            sha256: downloaded.sha256,
+            bytes: downloaded.bytes,
+        });
+        checkpoint.restart_parsing(&work_dir)?;
+        checkpoint.decompressed = false;
+        checkpoint.save(&work_dir)?;
+    }
+
+    // Decompressing and parsing take hours of CPU and disk time, which must not hold up
+    // the runtime's worker threads that the other months' downloads run on. Each month
+    // parses on all cores and buffers its own batches, so only a few may run at once.
+    let permit = parsing.acquire_owned().await?;
+    tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        process_archive(
+            year,
+            month,
+            &settings,
+            &PipelineConfig::default(),
+            checkpoint,
+            progress,
+        )
+    })
+    .await?
 }
 
-/// Process the entire flow for a given year and month:
-/// 1. Ensure the folder exists.
-/// 2. Download the compressed file (if not already present).
-/// 3. Decompress the file (if not already done).
-/// 4. Parse the PGN into [`ChessGame`] objects.
-/// 5. Save the data to Parquet in chunks of 100,000 games.
+/// Decompress a downloaded archive, parse it, and write its outputs and manifest,
+/// resuming from the checkpoint. Blocks until done.
 ///
 /// # Arguments
 ///
 /// * `year` - The year.
 /// * `month` - The month.
-pub async fn process_year_month(year: i32, month: i32) -> Result<()> {
-    ensure_folder_structure(year, month)?;
-    let work_dir = format!("lichess_data/{}/{}", year, format!("{:02}", month));
-    let url = construct_url(year, month);
-
+/// * `settings` - The data source and the outputs to write.
+/// * `config` - Batch and file sizes.
+/// * `checkpoint` - The month's checkpoint, with the download recorded.
+/// * `progress` - Receives the progress of each stage.
+fn process_archive(
+    year: i32,
+    month: i32,
+    settings: &RunSettings,
+    config: &PipelineConfig,
+    mut checkpoint: Checkpoint,
+    progress: Arc<dyn ProgressSink>,
+) -> Result<()> {
+    let root = settings.root.as_str();
+    let work_dir = format!("{}/{}/{:02}", root, year, month);
     let compressed_path = format!("{}/{}-{:02}.pgn.zst", work_dir, year, month);
     let pgn_path = format!("{}/{}-{:02}.pgn", work_dir, year, month);
 
-    if !Path::new(&compressed_path).exists() {
-        println!("Downloading data from {} to {}", url, compressed_path);
-        download_file(&url, &compressed_path).await?;
-        println!("Download completed.");
-    } else {
-        println!("Compressed file already exists: {}", compressed_path);
+    if !checkpoint.decompressed || !Path::new(&pgn_path).exists() {
+        // Offsets into the old PGN mean nothing for the new one.
+        checkpoint.restart_parsing(&work_dir)?;
+        let mut stage = StageProgress::new(progress.clone(), Stage::Decompress, year, month);
+        decompress_zst_file(&compressed_path, &pgn_path, &mut stage)?;
+        stage.finish(fs::metadata(&compressed_path)?.len(), 0);
+        checkpoint.decompressed = true;
+        checkpoint.save(&work_dir)?;
     }
 
-    if !Path::new(&pgn_path).exists() {
-        println!("Decompressing {} to {}", compressed_path, pgn_path);
-        decompress_zst_file(&compressed_path, &pgn_path)?;
-        println!("Decompression completed.");
-    } else {
-        println!("Decompressed PGN file already exists: {}", pgn_path);
-    }
+    checkpoint.for_outputs(&settings.outputs);
+    let mut writers = MonthWriters::new(year, month, settings, config, &checkpoint)?;
+    let (offset, games_read, games_parsed) =
+        (checkpoint.offset, checkpoint.games_read, checkpoint.games_parsed);
+    let mut reader = BufReader::new(fs::File::open(&pgn_path)?);
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut stage = StageProgress::new(progress.clone(), Stage::Parse, year, month);
+    stage.set_total_bytes(Some(fs::metadata(&pgn_path)?.len()));
+    let extra = settings.outputs.extra;
+    let parse = |text: &str| parse_pgn_game(text, &extra);
+    let stats = run_pipeline(reader, parse, config, |games, stats| {
+        for game in games {
+            writers.push(game, &mut checkpoint.files)?;
+        }
+        stage.update(offset + stats.bytes_read, games_parsed + stats.games_parsed);
+        if writers.games.buffered() >= config.games_per_file {
+            checkpoint.offset = offset + stats.bytes_read;
+            checkpoint.games_read = games_read + stats.games_read;
+            checkpoint.games_parsed = games_parsed + stats.games_parsed;
+            writers.commit(&mut checkpoint, &work_dir)?;
+        }
+        Ok(())
+    })?;
 
-    println!("Parsing PGN file: {}", pgn_path);
-    let games = parse_pgn_file(&pgn_path)?;
-    println!("Parsed {} games.", games.len());
+    let mut written = std::mem::take(&mut checkpoint.files);
+    written.extend(writers.games.finish()?);
+    if let Some(positions) = writers.positions {
+        written.extend(positions.finish()?);
+    }
+    if let Some(features) = writers.features {
+        written.extend(features.finish()?);
+    }
+    if let Some(ratings) = writers.ratings {
+        written.extend(ratings.finish()?);
+        let mut index = checkpoint.load_players(&work_dir)?;
+        index.merge(writers.players);
+        let mut players = players_to_dataframe(&index.into_players())?;
+        let part = write_month_table(
+            &mut players,
+            root,
+            "players",
+            year,
+            month,
+            settings.outputs.layout,
+            settings.outputs.format,
+        )?;
+        written.push(part);
+    }
+    if let Some(sqlite) = writers.sqlite {
+        written.push(WrittenPart {
+            table: "sqlite".to_string(),
+            path: sqlite_path(root, year, month),
+            rows: sqlite.finish()?,
+        });
+    }
+    // Totals over this run and any it resumed.
+    let stats = PipelineStats {
+        games_read: games_read + stats.games_read,
+        games_parsed: games_parsed + stats.games_parsed,
+        bytes_read: offset + stats.bytes_read,
+    };
+    stage.finish(stats.bytes_read, stats.games_parsed);
 
-    // Write games in chunks of 100,000.
-    let chunk_size = 100_000;
-    let mut file_counter = 0;
-    for chunk in games.chunks(chunk_size) {
-        file_counter += 1;
-        let parquet_path = format!(
-            "{}/{}-{:02}__{:03}.parquet",
-            work_dir, year, month, file_counter
-        );
-        println!("Writing {} games to {}", chunk.len(), parquet_path);
-        // write_games_to_parquet(chunk, &parquet_path)?;
+    // The manifest is written last, so its presence means the month is complete.
+    let source = checkpoint.source.clone().expect("the download was recorded");
+    let mut manifest = Manifest::new(year, month, source, settings.outputs);
+    manifest.games_read = stats.games_read;
+    manifest.games_parsed = stats.games_parsed;
+    manifest.games_rejected = stats.games_read - stats.games_parsed;
+    for part in &written {
+        manifest.add_file(root, part)?;
     }
+    manifest.write(&work_dir)?;
+    Checkpoint::remove(&work_dir)?;
 
-    println!("Finished processing data for {}/{}", year, month);
+    StageProgress::new(progress, Stage::Done, year, month).finish(0, stats.games_parsed);
     Ok(())
 }
 
-/// The main function spawns asynchronous tasks for each desired year and month.
-/// Years and months are filtered according to the rules:
-/// - For 2013, only months >= August are processed.
-/// - For 2017, only months <= April are processed.
+/// The value of a `--name=value` command-line argument, if given.
+fn arg_value(name: &str) -> Option<String> {
+    let prefix = format!("--{}=", name);
+    std::env::args().find_map(|arg| arg.strip_prefix(&prefix).map(str::to_string))
+}
+
+/// Read the `--sqlite`, `--positions`, `--features` and `--players` flags.
+fn extra_outputs_from_args() -> ExtraOutputs {
+    let flag = |name: &str| std::env::args().any(|arg| arg == name);
+    ExtraOutputs {
+        sqlite: flag("--sqlite"),
+        positions: flag("--positions"),
+        players: flag("--players"),
+        features: flag("--features"),
+    }
+}
+
+/// Read the output layout from a `--layout=flat|hive` argument, defaulting to flat.
+fn layout_from_args() -> Result<OutputLayout> {
+    match arg_value("layout") {
+        Some(layout) => OutputLayout::from_str(&layout)
+            .map_err(|_| anyhow!("Unknown output layout '{}', expected 'flat' or 'hive'", layout)),
+        None => Ok(OutputLayout::default()),
+    }
+}
+
+/// Read the Parquet writer settings from the `--compression=zstd:9`, `--row-group-size=N`,
+/// `--no-dictionary` and `--no-statistics` arguments.
+fn parquet_config_from_args() -> Result<ParquetWriterConfig> {
+    let mut config = ParquetWriterConfig::default();
+    if let Some(codec) = arg_value("compression") {
+        config.codec = ParquetCodec::from_str(&codec).map_err(|_| {
+            anyhow!(
+                "Unknown compression '{}', expected 'uncompressed', 'snappy', 'lz4' or 'zstd[:1-22]'",
+                codec
+            )
+        })?;
+    }
+    if let Some(size) = arg_value("row-group-size") {
+        config.row_group_size = match size.parse::<usize>()? {
+            0 => None,
+            size => Some(size),
+        };
+    }
+    config.dictionary = !std::env::args().any(|arg| arg == "--no-dictionary");
+    config.statistics = !std::env::args().any(|arg| arg == "--no-statistics");
+    Ok(config)
+}
+
+/// Read the output format from a `--format=parquet|arrow|csv|ndjson` argument,
+/// defaulting to Parquet.
+fn format_from_args() -> Result<OutputFormat> {
+    match arg_value("format") {
+        Some(format) => match OutputFormat::from_str(&format) {
+            Ok(OutputFormat::Parquet(_)) => Ok(OutputFormat::Parquet(parquet_config_from_args()?)),
+            Ok(format) => Ok(format),
+            Err(()) => Err(anyhow!(
+                "Unknown output format '{}', expected 'parquet', 'arrow', 'csv' or 'ndjson'",
+                format
+            )),
+        },
+        None => Ok(OutputFormat::Parquet(parquet_config_from_args()?)),
+    }
+}
+
+/// Choose how progress is shown from a `--progress=bar|json|none` argument: bars redrawn
+/// on standard error, one JSON event per line on standard output, or nothing. Defaults to
+/// bars.
+fn progress_from_args() -> Result<Arc<dyn ProgressSink>> {
+    match arg_value("progress").as_deref() {
+        None | Some("bar") => Ok(Arc::new(TerminalProgress::new())),
+        Some("json") => Ok(Arc::new(JsonLinesProgress::new(io::stdout()))),
+        Some("none") => Ok(Arc::new(())),
+        Some(progress) => Err(anyhow!(
+            "Unknown progress display '{}', expected 'bar', 'json' or 'none'",
+            progress
+        )),
+    }
+}
+
+/// Read the dataset from a `--dataset=standard` argument and the mirror to download it
+/// from, if any, from `--base-url`, defaulting to standard games from Lichess.
+///
+/// Other datasets are rejected: variant games are replayed with the variant's own rules
+/// and have no speed in their event, and the puzzles and evals are not game archives.
+fn source_from_args() -> Result<DataSource> {
+    let mut source = DataSource::default();
+    if let Some(kind) = arg_value("dataset") {
+        match DatasetKind::from_str(&kind) {
+            Ok(DatasetKind::Standard) => {}
+            Ok(DatasetKind::Variant(variant)) => {
+                return Err(anyhow!(
+                    "{} games cannot be processed yet, only standard ones",
+                    variant
+                ))
+            }
+            Ok(other) => return Err(anyhow!("The {} dataset is not a game archive", other)),
+            Err(()) => return Err(anyhow!("Unknown dataset '{}', expected 'standard'", kind)),
+        }
+    }
+    if let Some(base_url) = arg_value("base-url") {
+        source.base_url = base_url;
+    }
+    Ok(source)
+}
+
+/// Parse a `YYYY-MM` month, e.g. `2016-03`.
+fn parse_year_month(s: &str) -> Option<(i32, i32)> {
+    let (year, month) = s.split_once('-')?;
+    let (year, month) = (year.parse().ok()?, month.parse().ok()?);
+    (1..=12).contains(&month).then_some((year, month))
+}
+
+/// Every month from `first` to `last`, inclusive.
+fn month_range(first: (i32, i32), last: (i32, i32)) -> Vec<(i32, i32)> {
+    let mut months = Vec::new();
+    let (mut year, mut month) = first;
+    while (year, month) <= last {
+        months.push((year, month));
+        (year, month) = if month == 12 {
+            (year + 1, 1)
+        } else {
+            (year, month + 1)
+        };
+    }
+    months
+}
+
+/// The first and last month processed unless `--from` or `--to` is given.
+const DEFAULT_MONTHS: ((i32, i32), (i32, i32)) = ((2013, 8), (2017, 4));
+
+/// Read the months to process from `--from=YYYY-MM` and `--to=YYYY-MM` arguments,
+/// defaulting to [`DEFAULT_MONTHS`].
+fn months_from_args(kind: &DatasetKind) -> Result<Vec<(i32, i32)>> {
+    let month_arg = |name: &str| -> Result<Option<(i32, i32)>> {
+        arg_value(name)
+            .map(|value| {
+                parse_year_month(&value)
+                    .ok_or_else(|| anyhow!("Invalid month '{}', expected YYYY-MM", value))
+            })
+            .transpose()
+    };
+    let first = month_arg("from")?.unwrap_or(DEFAULT_MONTHS.0);
+    let last = month_arg("to")?.unwrap_or(DEFAULT_MONTHS.1);
+    if let Some(published) = kind.first_month().filter(|&published| first < published) {
+        return Err(anyhow!(
+            "The {} archives start in {}-{:02}",
+            kind,
+            published.0,
+            published.1
+        ));
+    }
+    let months = month_range(first, last);
+    if months.is_empty() {
+        return Err(anyhow!("No months between --from and --to"));
+    }
+    Ok(months)
+}
+
+/// Read the download limit from a `--max-downloads=N` argument.
+fn download_config_from_args() -> Result<DownloadConfig> {
+    let mut config = DownloadConfig::default();
+    if let Some(max) = arg_value("max-downloads") {
+        config.max_concurrent = max
+            .parse()
+            .map_err(|_| anyhow!("Invalid download limit '{}', expected a number", max))?;
+    }
+    Ok(config)
+}
+
+/// Read the number of months to decompress and parse at once from a `--parallel-months=N`
+/// argument, defaulting to one.
+fn parallel_months_from_args() -> Result<usize> {
+    match arg_value("parallel-months") {
+        Some(n) => match n.parse() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(anyhow!("Invalid month limit '{}', expected a positive number", n)),
+        },
+        None => Ok(1),
+    }
+}
+
+/// The main function spawns asynchronous tasks for each desired year and month: from
+/// August 2013 to April 2017, or from `--from=YYYY-MM` to `--to=YYYY-MM`.
+///
+/// Pass `--layout=hive` to write a Hive-partitioned dataset instead of one folder per month,
+/// and `--format=arrow|csv|ndjson` to write something other than Parquet. `--sqlite` also
+/// loads each month into a SQLite database, `--positions` writes the positions table,
+/// `--features` the ply-features table and `--players` the players and rating-history
+/// tables. Parquet output can be tuned with `--compression`, `--row-group-size` (0 for one
+/// row group per file), `--no-dictionary` and `--no-statistics`. At most two archives
+/// download at once, or `--max-downloads=N`, and one month is decompressed and parsed at a
+/// time, or `--parallel-months=N`. Progress is drawn as bars on standard error, or
+/// `--progress=json` writes it and any messages to standard output as JSON lines and
+/// `--progress=none` hides it.
+///
+/// `--base-url` downloads from a mirror of the Lichess database site, and `--output=DIR`
+/// writes somewhere other than `lichess_data`. Only standard games can be processed so far,
+/// so `--dataset` only accepts `standard`. Months whose files still have the size and
+/// modification time recorded in their manifest are skipped; `--verify-checksums` hashes
+/// the files instead.
 #[tokio::main]
 async fn main() -> Result<()> {
+    let source = source_from_args()?;
+    let months = months_from_args(&source.kind)?;
+    let settings = Arc::new(RunSettings {
+        root: arg_value("output").unwrap_or_else(|| source.kind.default_root()),
+        source,
+        outputs: OutputSettings {
+            layout: layout_from_args()?,
+            format: format_from_args()?,
+            extra: extra_outputs_from_args(),
+        },
+        verify_checksums: std::env::args().any(|arg| arg == "--verify-checksums"),
+    });
+    let downloads = DownloadScheduler::new(download_config_from_args()?);
+    let parsing = Arc::new(Semaphore::new(parallel_months_from_args()?));
+    let progress = progress_from_args()?;
+    let stale = remove_temp_files(&settings.root)?;
+    if stale > 0 {
+        progress.message(&format!(
+            "Removed {} unfinished files from an earlier run.",
+            stale
+        ));
+    }
     let mut tasks = FuturesUnordered::new();
 
-    for year in 2013..2018 {
-        for month in 1..=12 {
-            if year == 2013 && month < 8 {
-                continue;
-            }
-            if year == 2017 && month > 4 {
-                continue;
+    for (year, month) in months {
+        // Spawn a task for each year-month pair.
+        let fut = process_year_month(
+            year,
+            month,
+            settings.clone(),
+            downloads.clone(),
+            parsing.clone(),
+            progress.clone(),
+        );
+        let progress = progress.clone();
+        tasks.push(tokio::spawn(async move {
+            if let Err(e) = fut.await {
+                progress.message(&format!("Error processing {}/{}: {:?}", year, month, e));
             }
-            // Spawn a task for each year-month pair.
-            let fut = process_year_month(year, month);
-            tasks.push(tokio::spawn(async move {
-                if let Err(e) = fut.await {
-                    eprintln!("Error processing {}/{}: {:?}", year, month, e);
-                }
-            }));
-        }
+        }));
     }
 
     // Await all tasks.
@@ -376,11 +849,9 @@ async fn main() -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chess_rs::{GameType, Winner};
 
-    /// Test that a sample PGN game is correctly parsed.
-    #[test]
-    fn test_parse_pgn_game() {
-        let sample = r#"[Event "Rated Bullet game"]
+    const SAMPLE: &str = r#"[Event "Rated Bullet game"]
 [Site "https://lichess.org/QSgawA0K"]
 [White "ShahinMohammad"]
 [Black "Drummied"]
@@ -398,13 +869,23 @@ mod tests {
 
 1. d3 d5 2. g3 e6 3. Bg2 Nf6"#;
 
-        let game = parse_pgn_game(sample).expect("Failed to parse PGN game");
+    /// Test that a sample PGN game is correctly parsed.
+    #[test]
+    fn test_parse_pgn_game() {
+        let sample = SAMPLE;
+        let game = parse_pgn_game(sample, &ExtraOutputs::default())
+            .expect("Failed to parse PGN game")
+            .game;
         assert_eq!(game.url, "https://lichess.org/QSgawA0K");
+        assert_eq!(game.game_id, "QSgawA0K");
         assert_eq!(game.white_player_name, "ShahinMohammad");
         assert_eq!(game.black_player_name, "Drummied");
         assert_eq!(game.white_player_elo, 1525);
         assert_eq!(game.black_player_elo, 1458);
         assert_eq!(game.rating_diff, 67);
+        assert_eq!(game.white_rating_change, Some(-14));
+        assert_eq!(game.black_rating_change, Some(14));
+        assert_eq!(game.white_title, None);
         assert_eq!(game.game_type, GameType::Bullet);
         assert_eq!(game.winner, Some(Winner::Black));
         assert_eq!(game.termination_type, TerminationType::TimeForfeit);
@@ -418,5 +899,127 @@ mod tests {
         );
         assert_eq!(game.opening_name, "Mieses Opening");
         assert_eq!(game.opening_eco, "A00");
+        assert_eq!(game.moves.len(), 6);
+        assert_eq!(game.moves[4].san, "Bg2");
+        assert_eq!(game.moves[4].uci.as_deref(), Some("f1g2"));
+        assert_eq!(game.ply_count, 6);
+        assert_eq!(
+            game.final_fen.as_deref(),
+            Some("rnbqkb1r/ppp2ppp/4pn2/3p4/8/3P2P1/PPP1PPBP/RNBQK1NR w KQkq - 2 4")
+        );
+        assert_eq!(game.board_outcome, None);
+        assert_eq!(game.white_analysis, None);
+
+        let unfinished = sample.replace("[Result \"0-1\"]", "[Result \"*\"]");
+        let unfinished = parse_pgn_game(&unfinished, &ExtraOutputs::default())
+            .expect("Failed to parse unfinished game")
+            .game;
+        assert_eq!(unfinished.winner, None);
+        assert_eq!(unfinished.termination_type, TerminationType::Unterminated);
+    }
+
+    #[test]
+    fn test_parse_positions() {
+        let extra = ExtraOutputs {
+            positions: true,
+            ..ExtraOutputs::default()
+        };
+        let parsed = parse_pgn_game(SAMPLE, &extra).unwrap();
+        assert_eq!(parsed.positions.len(), 6);
+        assert_eq!(parsed.positions[4].game_id, "QSgawA0K");
+        assert_eq!(parsed.positions[4].uci.as_deref(), Some("f1g2"));
+
+        let parsed = parse_pgn_game(SAMPLE, &ExtraOutputs::default()).unwrap();
+        assert!(parsed.positions.is_empty());
+        let illegal = SAMPLE.replace("3. Bg2", "3. Bh7");
+        assert!(parse_pgn_game(&illegal, &extra).unwrap().positions.is_empty());
+    }
+
+    #[test]
+    fn test_parse_ply_features() {
+        let extra = ExtraOutputs {
+            features: true,
+            ..ExtraOutputs::default()
+        };
+        let parsed = parse_pgn_game(SAMPLE, &extra).unwrap();
+        assert!(parsed.positions.is_empty());
+        assert_eq!(parsed.features.len(), 6);
+        assert_eq!(parsed.features[5].game_id, "QSgawA0K");
+        assert_eq!(parsed.features[5].game_type, GameType::Bullet);
+        assert!(!parsed.features[5].white_to_move);
+    }
+
+    #[test]
+    fn test_month_range() {
+        assert_eq!(parse_year_month("2013-11"), Some((2013, 11)));
+        assert_eq!(parse_year_month("2013-13"), None);
+        assert_eq!(parse_year_month("2013"), None);
+        assert_eq!(
+            month_range((2013, 11), (2014, 2)),
+            vec![(2013, 11), (2013, 12), (2014, 1), (2014, 2)]
+        );
+        assert!(month_range((2014, 2), (2013, 11)).is_empty());
+    }
+
+    #[test]
+    fn test_redecompress_restarts_parsing() {
+        let root = std::env::temp_dir().join(format!("chess_rs_restart_{}", std::process::id()));
+        let root = root.to_str().unwrap().to_string();
+        let work_dir = format!("{}/2016/03", root);
+        fs::create_dir_all(&work_dir).unwrap();
+        let games: Vec<String> = ["a", "b", "c"]
+            .iter()
+            .map(|id| SAMPLE.replace("QSgawA0K", id) + "\n\n")
+            .collect();
+        let archive = zstd::encode_all(games.concat().as_bytes(), 0).unwrap();
+        fs::write(format!("{}/2016-03.pgn.zst", work_dir), archive).unwrap();
+        let settings = RunSettings {
+            source: DataSource::default(),
+            root: root.clone(),
+            outputs: OutputSettings {
+                extra: ExtraOutputs {
+                    sqlite: true,
+                    ..ExtraOutputs::default()
+                },
+                ..OutputSettings::default()
+            },
+            verify_checksums: false,
+        };
+        let config = PipelineConfig::builder()
+            .batch_size(1)
+            .games_per_file(1)
+            .build()
+            .unwrap();
+
+        // A run that was interrupted after committing the first game, and whose PGN
+        // has to be decompressed again.
+        let mut checkpoint = Checkpoint {
+            source: Some(ManifestSource {
+                url: "https://database.lichess.org/x.pgn.zst".to_string(),
+                sha256: "00".to_string(),
+                bytes: 0,
+            }),
+            decompressed: true,
+            ..Checkpoint::default()
+        };
+        checkpoint.for_outputs(&settings.outputs);
+        let mut writers = MonthWriters::new(2016, 3, &settings, &config, &checkpoint).unwrap();
+        let first = parse_pgn_game(&games[0], &settings.outputs.extra).unwrap();
+        writers.push(first, &mut checkpoint.files).unwrap();
+        checkpoint.offset = games[0].len() as u64;
+        checkpoint.games_read = 1;
+        checkpoint.games_parsed = 1;
+        writers.commit(&mut checkpoint, &work_dir).unwrap();
+        assert_eq!(checkpoint.files.len(), 1);
+
+        process_archive(2016, 3, &settings, &config, checkpoint, Arc::new(())).unwrap();
+        let manifest = read_manifest(&work_dir).unwrap();
+        assert_eq!(manifest.games_read, 3);
+        assert_eq!(manifest.games_parsed, 3);
+        assert_eq!(manifest.table_rows("games"), 3);
+        assert_eq!(manifest.table_rows("sqlite"), 3);
+        assert_eq!(manifest.files.len(), 4);
+        manifest.verify(&root).unwrap();
+        fs::remove_dir_all(root).unwrap();
     }
 }
@@ -0,0 +1,305 @@
+use std::fs;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::atomic::write_file_atomically;
+use crate::dataframe::SCHEMA_VERSION;
+use crate::output::{OutputSettings, WrittenPart};
+
+/// Name of the manifest file in each month directory.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// The archive a month was parsed from.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ManifestSource {
+    pub url: String,
+    /// Hex-encoded SHA-256 of the compressed archive.
+    pub sha256: String,
+    pub bytes: u64,
+}
+
+/// An output file of a month.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ManifestFile {
+    /// The table the file belongs to, e.g. `games` or `positions`, or `sqlite` for the
+    /// SQLite database.
+    pub table: String,
+    /// Path relative to the output root.
+    pub path: String,
+    pub rows: usize,
+    pub bytes: u64,
+    /// Last modification time of the file, in RFC 3339 format.
+    pub modified: String,
+    /// Hex-encoded SHA-256 of the file.
+    pub sha256: String,
+}
+
+/// What a run produced for one month, written to `manifest.json` in the month directory
+/// once every output file is complete.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Manifest {
+    pub year: i32,
+    pub month: i32,
+    /// Version of the crate that parsed the games.
+    pub parser_version: String,
+    /// Version of the output table schemas, see [`SCHEMA_VERSION`].
+    pub schema_version: u32,
+    pub source: ManifestSource,
+    /// The settings the output files were written with.
+    pub outputs: OutputSettings,
+    /// PGN game blocks in the archive.
+    pub games_read: usize,
+    /// Games that parsed and were written.
+    pub games_parsed: usize,
+    /// PGN game blocks that were skipped because a header was missing or malformed.
+    /// Unfinished games are parsed, not rejected.
+    pub games_rejected: usize,
+    pub files: Vec<ManifestFile>,
+    /// When the manifest was written, in RFC 3339 format.
+    pub created_at: String,
+}
+
+impl Manifest {
+    /// A manifest for a month parsed by this version of the crate.
+    pub fn new(year: i32, month: i32, source: ManifestSource, outputs: OutputSettings) -> Self {
+        Self {
+            year,
+            month,
+            parser_version: env!("CARGO_PKG_VERSION").to_string(),
+            schema_version: SCHEMA_VERSION,
+            source,
+            outputs,
+            games_read: 0,
+            games_parsed: 0,
+            games_rejected: 0,
+            files: Vec::new(),
+            created_at: String::new(),
+        }
+    }
+
+    /// Record an output file, with its size, modification time and checksum on disk.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The output root the file's path is made relative to.
+    /// * `part` - The written file.
+    pub fn add_file(&mut self, root: &str, part: &WrittenPart) -> Result<()> {
+        let metadata = fs::metadata(&part.path)?;
+        let sha256 = sha256_file(&part.path)?;
+        let path = Path::new(&part.path)
+            .strip_prefix(root)
+            .unwrap_or(Path::new(&part.path));
+        self.files.push(ManifestFile {
+            table: part.table.clone(),
+            path: path.to_string_lossy().into_owned(),
+            rows: part.rows,
+            bytes: metadata.len(),
+            modified: modified_time(&metadata)?,
+            sha256,
+        });
+        Ok(())
+    }
+
+    /// The number of rows written to a table, across all of its files.
+    pub fn table_rows(&self, table: &str) -> usize {
+        self.files
+            .iter()
+            .filter(|f| f.table == table)
+            .map(|f| f.rows)
+            .sum()
+    }
+
+    /// Write the manifest to `manifest.json` in a month directory, stamping its creation time.
+    pub fn write(&mut self, month_dir: &str) -> Result<()> {
+        self.created_at = chrono::Utc::now().to_rfc3339();
+        let path = Path::new(month_dir).join(MANIFEST_FILE);
+        write_file_atomically(&path.to_string_lossy(), serde_json::to_string_pretty(self)?)
+    }
+
+    /// Check that every file listed in the manifest exists under `root` with the recorded
+    /// size and modification time, and that the parsed games were all written to the games
+    /// table. This reads no file contents; see [`verify_checksums`](Self::verify_checksums).
+    pub fn verify(&self, root: &str) -> Result<()> {
+        self.check(root, false)
+    }
+
+    /// Like [`verify`](Self::verify), but compare every file's checksum instead of its
+    /// modification time, so files that were copied or touched but not changed still pass.
+    pub fn verify_checksums(&self, root: &str) -> Result<()> {
+        self.check(root, true)
+    }
+
+    fn check(&self, root: &str, checksums: bool) -> Result<()> {
+        if self.schema_version != SCHEMA_VERSION {
+            return Err(anyhow!(
+                "Manifest has schema version {}, expected {}",
+                self.schema_version,
+                SCHEMA_VERSION
+            ));
+        }
+        for file in &self.files {
+            let path = Path::new(root).join(&file.path);
+            let metadata = fs::metadata(&path)
+                .map_err(|e| anyhow!("Missing output file {}: {}", path.display(), e))?;
+            let bytes = metadata.len();
+            if bytes != file.bytes {
+                return Err(anyhow!(
+                    "Output file {} has {} bytes, expected {}",
+                    path.display(),
+                    bytes,
+                    file.bytes
+                ));
+            }
+            if checksums {
+                if sha256_file(&path.to_string_lossy())? != file.sha256 {
+                    return Err(anyhow!(
+                        "Output file {} does not match its checksum",
+                        path.display()
+                    ));
+                }
+            } else if modified_time(&metadata)? != file.modified {
+                return Err(anyhow!(
+                    "Output file {} was modified after the manifest was written",
+                    path.display()
+                ));
+            }
+        }
+        let games = self.table_rows("games");
+        if games != self.games_parsed {
+            return Err(anyhow!(
+                "Games table has {} rows, expected {}",
+                games,
+                self.games_parsed
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Read the manifest of a month directory, e.g. `lichess_data/2016/03`.
+///
+/// # Errors
+///
+/// Fails if the month has no manifest, which means it was never completed.
+pub fn read_manifest(month_dir: &str) -> Result<Manifest> {
+    let path = Path::new(month_dir).join(MANIFEST_FILE);
+    let json =
+        fs::read_to_string(&path).map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+/// The modification time of a file, in RFC 3339 format with the full precision the file
+/// system records.
+fn modified_time(metadata: &fs::Metadata) -> Result<String> {
+    let modified: DateTime<Utc> = metadata.modified()?.into();
+    Ok(modified.to_rfc3339_opts(SecondsFormat::Nanos, true))
+}
+
+/// The hex-encoded SHA-256 of a file's contents.
+pub fn sha256_file(path: &str) -> Result<String> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+/// Lowercase hex encoding of bytes, e.g. a digest.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_sha256_file() {
+        let path = std::env::temp_dir().join(format!("chess_rs_sha_{}", std::process::id()));
+        fs::write(&path, "abc").unwrap();
+        let sha = sha256_file(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            sha,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_write_read_and_verify_manifest() {
+        let root = std::env::temp_dir().join(format!("chess_rs_manifest_{}", std::process::id()));
+        let month_dir = root.join("2016/03");
+        fs::create_dir_all(&month_dir).unwrap();
+        let root = root.to_str().unwrap();
+        let month_dir = month_dir.to_str().unwrap();
+        let part_path = format!("{}/2016-03__001.parquet", month_dir);
+        fs::write(&part_path, "12345").unwrap();
+
+        let mut manifest = Manifest::new(
+            2016,
+            3,
+            ManifestSource {
+                url: "https://database.lichess.org/x.pgn.zst".to_string(),
+                sha256: "00".to_string(),
+                bytes: 10,
+            },
+            OutputSettings::default(),
+        );
+        manifest.games_read = 3;
+        manifest.games_parsed = 2;
+        manifest.games_rejected = 1;
+        manifest
+            .add_file(
+                root,
+                &WrittenPart {
+                    table: "games".to_string(),
+                    path: part_path.clone(),
+                    rows: 2,
+                },
+            )
+            .unwrap();
+        manifest.write(month_dir).unwrap();
+
+        let read = read_manifest(month_dir).unwrap();
+        assert_eq!(read, manifest);
+        assert_eq!(read.files[0].path, "2016/03/2016-03__001.parquet");
+        assert_eq!(read.files[0].bytes, 5);
+        assert_eq!(read.files[0].sha256, sha256_file(&part_path).unwrap());
+        read.verify(root).unwrap();
+        read.verify_checksums(root).unwrap();
+
+        let modified = fs::metadata(&part_path).unwrap().modified().unwrap();
+        let rewrite = |contents: &str, modified: SystemTime| {
+            fs::write(&part_path, contents).unwrap();
+            let file = fs::File::options().write(true).open(&part_path).unwrap();
+            file.set_modified(modified).unwrap();
+        };
+        // Changed in place, keeping the size and modification time: only the checksum
+        // catches it.
+        rewrite("12346", modified);
+        read.verify(root).unwrap();
+        assert!(read.verify_checksums(root).is_err());
+        // Touched but unchanged: only the checksum shows it is still the same file.
+        rewrite("12345", modified + Duration::from_secs(1));
+        assert!(read.verify(root).is_err());
+        read.verify_checksums(root).unwrap();
+
+        fs::write(&part_path, "123").unwrap();
+        assert!(read.verify(root).is_err());
+        assert!(read.verify_checksums(root).is_err());
+        fs::remove_dir_all(root).unwrap();
+        assert!(read_manifest(month_dir).is_err());
+    }
+}
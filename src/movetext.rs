@@ -0,0 +1,227 @@
+use std::{fmt::{self, Display, Formatter}, str::FromStr, time::Duration};
+
+/// An engine evaluation attached to a move via a `[%eval ...]` comment.
+///
+/// Evaluations are always from White's point of view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eval {
+    /// Centipawn score, e.g. `[%eval 0.17]` is `Centipawns(17)`.
+    Centipawns(i32),
+    /// Forced mate in `n` moves, negative when Black mates, e.g. `[%eval #-3]`.
+    Mate(i32),
+}
+
+/// Mate scores are stored as integers at this distance from zero, minus the number of moves
+/// to mate, so they sort above every centipawn score (see [`Eval::to_encoded`]).
+pub const MATE_ENCODING: i32 = 100_000;
+
+impl Eval {
+    /// Encode the evaluation as a single integer: centipawns as-is, and mate in `n` as
+    /// `MATE_ENCODING - n` (or its negation when Black mates).
+    pub fn to_encoded(&self) -> i32 {
+        match *self {
+            Self::Centipawns(cp) => cp,
+            Self::Mate(n) if n < 0 => -(MATE_ENCODING + n),
+            Self::Mate(n) => MATE_ENCODING - n,
+        }
+    }
+
+    /// Decode an evaluation written by [`Eval::to_encoded`].
+    pub fn from_encoded(value: i32) -> Self {
+        // Nobody announces a mate in more than a thousand moves.
+        if value.abs() > MATE_ENCODING - 1000 {
+            Self::Mate(value.signum() * (MATE_ENCODING - value.abs()))
+        } else {
+            Self::Centipawns(value)
+        }
+    }
+}
+
+impl FromStr for Eval {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(mate) = s.strip_prefix('#') {
+            return mate.parse().map(Self::Mate).map_err(|_| ());
+        }
+        let pawns: f64 = s.parse().map_err(|_| ())?;
+        Ok(Self::Centipawns((pawns * 100.0).round() as i32))
+    }
+}
+
+impl Display for Eval {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Centipawns(cp) => write!(f, "{:.2}", *cp as f64 / 100.0),
+            Self::Mate(n) => write!(f, "#{}", n),
+        }
+    }
+}
+
+/// A single half-move from the movetext section of a PGN game.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MoveRecord {
+    /// The move in Standard Algebraic Notation, with annotation glyphs (`!`, `?`) removed.
+    pub san: String,
+    /// Clock time remaining after the move, from a `[%clk h:mm:ss]` comment.
+    pub clock: Option<Duration>,
+    /// Engine evaluation after the move, from a `[%eval ...]` comment.
+    pub eval: Option<Eval>,
+    /// The move in UCI notation, filled in once the game has been replayed.
+    pub uci: Option<String>,
+}
+
+impl MoveRecord {
+    pub fn new(san: &str) -> Self {
+        Self {
+            san: san.to_string(),
+            clock: None,
+            eval: None,
+            uci: None,
+        }
+    }
+}
+
+/// Parse a clock annotation of the form `h:mm:ss` (optionally with fractional seconds).
+pub fn parse_clock(s: &str) -> Option<Duration> {
+    let parts: Vec<&str> = s.trim().split(':').collect();
+    if parts.len() != 3 {
+        return None;
+    }
+    let hours: u64 = parts[0].parse().ok()?;
+    let minutes: u64 = parts[1].parse().ok()?;
+    let seconds: f64 = parts[2].parse().ok()?;
+    Some(Duration::from_secs(hours * 3600 + minutes * 60) + Duration::from_secs_f64(seconds))
+}
+
+/// Apply the `[%clk ...]` and `[%eval ...]` commands found in a comment to a move.
+fn apply_comment(comment: &str, record: &mut MoveRecord) {
+    let mut rest = comment;
+    while let Some(start) = rest.find("[%") {
+        let Some(len) = rest[start..].find(']') else {
+            break;
+        };
+        let command = &rest[start + 2..start + len];
+        if let Some(clock) = command.strip_prefix("clk ") {
+            record.clock = parse_clock(clock);
+        } else if let Some(eval) = command.strip_prefix("eval ") {
+            // Lichess may append the search depth, e.g. `[%eval 0.25,23]`.
+            let eval = eval.split(',').next().unwrap_or(eval);
+            record.eval = Eval::from_str(eval).ok();
+        }
+        rest = &rest[start + len + 1..];
+    }
+}
+
+/// Whether a token is a game termination marker rather than a move.
+fn is_result_token(token: &str) -> bool {
+    matches!(token, "1-0" | "0-1" | "1/2-1/2" | "*")
+}
+
+/// Parse the movetext section of a PGN game into a list of [`MoveRecord`]s.
+///
+/// Move numbers, NAGs, results and variations are skipped; comments are only
+/// inspected for `[%clk ...]` and `[%eval ...]` commands, which are attached to
+/// the move they follow.
+pub fn parse_movetext(movetext: &str) -> Vec<MoveRecord> {
+    let mut moves: Vec<MoveRecord> = Vec::new();
+    let mut chars = movetext.char_indices().peekable();
+    let mut variation_depth = 0;
+
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '{' => {
+                let end = movetext[start..]
+                    .find('}')
+                    .map(|i| start + i)
+                    .unwrap_or(movetext.len());
+                if variation_depth == 0 {
+                    if let Some(last) = moves.last_mut() {
+                        apply_comment(&movetext[start + 1..end], last);
+                    }
+                }
+                while chars.next_if(|&(i, _)| i <= end).is_some() {}
+            }
+            ';' => {
+                // Rest-of-line comment.
+                while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+            }
+            '(' => variation_depth += 1,
+            ')' => variation_depth -= 1,
+            c if c.is_whitespace() => {}
+            _ => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, c)) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '{' | '(' | ')' | ';') {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                if variation_depth > 0 {
+                    continue;
+                }
+                let token = &movetext[start..end];
+                // Strip a leading move number such as `12.` or `12...`.
+                let token = token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+                let token = token.trim_end_matches(['!', '?']);
+                if token.is_empty() || token.starts_with('$') || is_result_token(&movetext[start..end]) {
+                    continue;
+                }
+                moves.push(MoveRecord::new(token));
+            }
+        }
+    }
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval_from_str() {
+        assert_eq!(Eval::from_str("0.17"), Ok(Eval::Centipawns(17)));
+        assert_eq!(Eval::from_str("-1.5"), Ok(Eval::Centipawns(-150)));
+        assert_eq!(Eval::from_str("#-3"), Ok(Eval::Mate(-3)));
+        assert_eq!(Eval::from_str("invalid"), Err(()));
+    }
+
+    #[test]
+    fn test_eval_encoding() {
+        for eval in [Eval::Centipawns(-250), Eval::Centipawns(0), Eval::Mate(3), Eval::Mate(-1)] {
+            assert_eq!(Eval::from_encoded(eval.to_encoded()), eval);
+        }
+        assert!(Eval::Mate(2).to_encoded() > Eval::Mate(5).to_encoded());
+        assert!(Eval::Mate(-2).to_encoded() < Eval::Centipawns(-5000).to_encoded());
+    }
+
+    #[test]
+    fn test_parse_clock() {
+        assert_eq!(parse_clock("0:01:00"), Some(Duration::from_secs(60)));
+        assert_eq!(parse_clock("1:02:03"), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_clock("invalid"), None);
+    }
+
+    #[test]
+    fn test_parse_movetext_plain() {
+        let moves = parse_movetext("1. d3 d5 2. g3 e6 3. Bg2 Nf6?! 0-1");
+        let sans: Vec<&str> = moves.iter().map(|m| m.san.as_str()).collect();
+        assert_eq!(sans, vec!["d3", "d5", "g3", "e6", "Bg2", "Nf6"]);
+    }
+
+    #[test]
+    fn test_parse_movetext_annotations() {
+        let moves = parse_movetext(
+            "1. e4 { [%eval 0.17] [%clk 0:00:30] } 1... e5 { [%eval #-3] [%clk 0:00:29] } \
+             2. Nf3 $1 (2. f4 exf4) 2... Nc6 1/2-1/2",
+        );
+        assert_eq!(moves.len(), 4);
+        assert_eq!(moves[0].eval, Some(Eval::Centipawns(17)));
+        assert_eq!(moves[0].clock, Some(Duration::from_secs(30)));
+        assert_eq!(moves[1].eval, Some(Eval::Mate(-3)));
+        assert_eq!(moves[2].san, "Nf3");
+        assert_eq!(moves[2].clock, None);
+        assert_eq!(moves[3].san, "Nc6");
+    }
+}
@@ -0,0 +1,663 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Result;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::atomic::write_atomically;
+use crate::dataframe::games_to_dataframe;
+use crate::parquet::{write_dataframe_to_parquet, ParquetWriterConfig};
+use crate::{ChessGame, GameType};
+
+/// The file format games are written in. Every format carries the columns of
+/// [`game_schema`](crate::dataframe::game_schema).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutputFormat {
+    Parquet(ParquetWriterConfig),
+    /// Arrow IPC (Feather v2) files, for zero-copy loading.
+    ArrowIpc,
+    /// CSV with a header row. Move lists are stored as space-separated strings,
+    /// with clocks in milliseconds and evals encoded as in [`Eval::to_encoded`](crate::movetext::Eval::to_encoded).
+    Csv,
+    /// One JSON object per game and line, with clocks in milliseconds.
+    Ndjson,
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        Self::Parquet(ParquetWriterConfig::default())
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = ();
+    /// Parse a format name. Parquet gets the default [`ParquetWriterConfig`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "parquet" => Ok(Self::default()),
+            "arrow" | "ipc" => Ok(Self::ArrowIpc),
+            "csv" => Ok(Self::Csv),
+            "ndjson" | "jsonl" => Ok(Self::Ndjson),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Parquet(_) => write!(f, "parquet"),
+            Self::ArrowIpc => write!(f, "arrow"),
+            Self::Csv => write!(f, "csv"),
+            Self::Ndjson => write!(f, "ndjson"),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// The file extension, without the dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Parquet(_) => "parquet",
+            Self::ArrowIpc => "arrow",
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+        }
+    }
+
+    /// Write a DataFrame to a file in this format. The file only appears once complete.
+    pub fn write_dataframe(&self, df: &mut DataFrame, output_path: &str) -> Result<()> {
+        match self {
+            Self::Parquet(config) => write_dataframe_to_parquet(df, output_path, config),
+            Self::ArrowIpc => {
+                write_atomically(output_path, |file| Ok(IpcWriter::new(file).finish(df)?))
+            }
+            Self::Csv => {
+                let mut df = flatten_for_csv(df)?;
+                write_atomically(
+                    output_path,
+                    |file| Ok(CsvWriter::new(file).finish(&mut df)?),
+                )
+            }
+            Self::Ndjson => {
+                let mut df = to_plain_types(df)?;
+                write_atomically(output_path, |file| {
+                    Ok(JsonWriter::new(file)
+                        .with_json_format(JsonFormat::JsonLines)
+                        .finish(&mut df)?)
+                })
+            }
+        }
+    }
+}
+
+/// Cast categorical and time columns to plain strings and clocks to milliseconds, for
+/// text formats without dictionary or time types.
+fn to_plain_types(df: &DataFrame) -> Result<DataFrame> {
+    let columns = df
+        .get_columns()
+        .iter()
+        .map(|s| match s.dtype() {
+            DataType::Categorical(_) | DataType::Time => s.cast(&DataType::Utf8),
+            DataType::List(inner) if matches!(**inner, DataType::Duration(_)) => {
+                s.cast(&DataType::List(Box::new(DataType::Int64)))
+            }
+            _ => Ok(s.clone()),
+        })
+        .collect::<PolarsResult<Vec<_>>>()?;
+    Ok(DataFrame::new(columns)?)
+}
+
+/// Replace list columns with space-separated strings, since CSV has no nested values.
+/// Missing entries are written as `null`, so the lists of a game stay aligned by ply.
+fn flatten_for_csv(df: &DataFrame) -> Result<DataFrame> {
+    let columns = to_plain_types(df)?
+        .get_columns()
+        .iter()
+        .map(|s| {
+            if !matches!(s.dtype(), DataType::List(_)) {
+                return Ok(s.clone());
+            }
+            let joined: Vec<Option<String>> = s
+                .list()?
+                .into_iter()
+                .map(|values| {
+                    values
+                        .map(|values| -> PolarsResult<String> {
+                            let values = values.to_physical_repr().cast(&DataType::Utf8)?;
+                            let values: Vec<&str> = values
+                                .utf8()?
+                                .into_iter()
+                                .map(|value| value.unwrap_or("null"))
+                                .collect();
+                            Ok(values.join(" "))
+                        })
+                        .transpose()
+                })
+                .collect::<PolarsResult<_>>()?;
+            Ok(Series::new(s.name(), joined))
+        })
+        .collect::<PolarsResult<Vec<_>>>()?;
+    Ok(DataFrame::new(columns)?)
+}
+
+/// How output files are laid out on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum OutputLayout {
+    /// `{root}/YYYY/MM/YYYY-MM__NNN.{ext}`, next to the downloaded archive.
+    #[default]
+    Flat,
+    /// `{root}/games/year=YYYY/month=MM/game_type=Blitz/part-NNN.{ext}`, so query engines
+    /// can prune files by partition. The `game_type` column is only stored in the path.
+    Hive,
+}
+
+impl FromStr for OutputLayout {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "flat" => Ok(Self::Flat),
+            "hive" => Ok(Self::Hive),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for OutputLayout {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Flat => write!(f, "flat"),
+            Self::Hive => write!(f, "hive"),
+        }
+    }
+}
+
+/// Which outputs to write besides the games table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtraOutputs {
+    /// Load the games into `{year}-{MM}.sqlite` next to the archive.
+    pub sqlite: bool,
+    /// Write the positions table, one row per ply.
+    pub positions: bool,
+    /// Write the players table and the rating-history table.
+    pub players: bool,
+    /// Write the ply-features table, one row per ply.
+    #[serde(default)]
+    pub features: bool,
+}
+
+/// Everything that decides which files a month's output consists of. It is recorded in
+/// the month's checkpoint and manifest, so output written with other settings is redone.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputSettings {
+    pub layout: OutputLayout,
+    pub format: OutputFormat,
+    pub extra: ExtraOutputs,
+}
+
+/// The Hive partition directory of a table for a month and game type.
+///
+/// # Arguments
+///
+/// * `root` - The output root, e.g. `lichess_data`.
+/// * `table` - The table name, e.g. `games`.
+pub fn hive_partition_dir(
+    root: &str,
+    table: &str,
+    year: i32,
+    month: i32,
+    game_type: &GameType,
+) -> String {
+    format!(
+        "{}/{}/year={}/month={:02}/game_type={}",
+        root, table, year, month, game_type
+    )
+}
+
+impl OutputLayout {
+    /// The path of the `part`-th (1-based) file of a table for a month, or for one game
+    /// type of a month.
+    #[allow(clippy::too_many_arguments)]
+    pub fn part_path(
+        &self,
+        root: &str,
+        table: &str,
+        year: i32,
+        month: i32,
+        game_type: &GameType,
+        part: usize,
+        format: OutputFormat,
+    ) -> String {
+        match self {
+            // The games table predates the others and keeps its unprefixed file names.
+            Self::Flat if table == ChessGame::TABLE => format!(
+                "{}/{}/{:02}/{}-{:02}__{:03}.{}",
+                root,
+                year,
+                month,
+                year,
+                month,
+                part,
+                format.extension()
+            ),
+            Self::Flat => format!(
+                "{}/{}/{:02}/{}-{:02}_{}__{:03}.{}",
+                root,
+                year,
+                month,
+                year,
+                month,
+                table,
+                part,
+                format.extension()
+            ),
+            Self::Hive => format!(
+                "{}/part-{:03}.{}",
+                hive_partition_dir(root, table, year, month, game_type),
+                part,
+                format.extension()
+            ),
+        }
+    }
+}
+
+impl OutputLayout {
+    /// The path of a table written as a single file per month, such as the players table.
+    pub fn month_path(
+        &self,
+        root: &str,
+        table: &str,
+        year: i32,
+        month: i32,
+        format: OutputFormat,
+    ) -> String {
+        match self {
+            Self::Flat => format!(
+                "{}/{}/{:02}/{}-{:02}_{}.{}",
+                root,
+                year,
+                month,
+                year,
+                month,
+                table,
+                format.extension()
+            ),
+            Self::Hive => format!(
+                "{}/{}/year={}/month={:02}/part-001.{}",
+                root,
+                table,
+                year,
+                month,
+                format.extension()
+            ),
+        }
+    }
+}
+
+/// Write a month's worth of a table to a single file, see [`OutputLayout::month_path`].
+#[allow(clippy::too_many_arguments)]
+pub fn write_month_table(
+    df: &mut DataFrame,
+    root: &str,
+    table: &str,
+    year: i32,
+    month: i32,
+    layout: OutputLayout,
+    format: OutputFormat,
+) -> Result<WrittenPart> {
+    let path = layout.month_path(root, table, year, month, format);
+    if let Some(dir) = Path::new(&path).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    format.write_dataframe(df, &path)?;
+    Ok(WrittenPart {
+        table: table.to_string(),
+        path,
+        rows: df.height(),
+    })
+}
+
+/// A row of an output table written by a [`TableWriter`].
+pub trait TableRow: Sized {
+    /// The table name, used in file names and as the Hive dataset directory.
+    const TABLE: &'static str;
+
+    /// The game type the row belongs to, used as the Hive partition.
+    fn game_type(&self) -> &GameType;
+
+    /// Convert a batch of rows into a DataFrame.
+    fn to_dataframe(rows: &[Self]) -> PolarsResult<DataFrame>;
+}
+
+impl TableRow for ChessGame {
+    const TABLE: &'static str = "games";
+
+    fn game_type(&self) -> &GameType {
+        &self.game_type
+    }
+
+    fn to_dataframe(rows: &[Self]) -> PolarsResult<DataFrame> {
+        games_to_dataframe(rows)
+    }
+}
+
+/// A file written by a [`TableWriter`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WrittenPart {
+    pub table: String,
+    pub path: String,
+    pub rows: usize,
+}
+
+/// Buffers the rows of a table for a month and writes them to files of at most
+/// `chunk_size` rows each, in the chosen [`OutputLayout`] and [`OutputFormat`].
+pub struct TableWriter<T> {
+    root: String,
+    year: i32,
+    month: i32,
+    layout: OutputLayout,
+    format: OutputFormat,
+    chunk_size: usize,
+    /// Buffered rows per partition: a single partition for the flat layout,
+    /// one per game type for Hive.
+    buffers: BTreeMap<String, (GameType, Vec<T>)>,
+    /// Number of files written so far per partition.
+    parts: HashMap<String, usize>,
+}
+
+/// Writes the games table.
+pub type GameWriter = TableWriter<ChessGame>;
+
+impl<T: TableRow> TableWriter<T> {
+    pub fn new(
+        root: &str,
+        year: i32,
+        month: i32,
+        layout: OutputLayout,
+        format: OutputFormat,
+        chunk_size: usize,
+    ) -> Self {
+        Self {
+            root: root.to_string(),
+            year,
+            month,
+            layout,
+            format,
+            chunk_size,
+            buffers: BTreeMap::new(),
+            parts: HashMap::new(),
+        }
+    }
+
+    fn partition_key(&self, row: &T) -> String {
+        match self.layout {
+            OutputLayout::Flat => String::new(),
+            OutputLayout::Hive => row.game_type().to_string(),
+        }
+    }
+
+    /// Add a row, writing its partition's buffer out if it is full.
+    ///
+    /// # Returns
+    ///
+    /// * The file that was written, if any.
+    pub fn push(&mut self, row: T) -> Result<Option<WrittenPart>> {
+        let key = self.partition_key(&row);
+        let (_, buffer) = self
+            .buffers
+            .entry(key.clone())
+            .or_insert_with(|| (row.game_type().clone(), Vec::new()));
+        buffer.push(row);
+        if buffer.len() >= self.chunk_size {
+            return self.flush(&key).map(Some);
+        }
+        Ok(None)
+    }
+
+    fn flush(&mut self, key: &str) -> Result<WrittenPart> {
+        let (game_type, rows) = self
+            .buffers
+            .remove(key)
+            .expect("flushing a known partition");
+        let part = self.parts.entry(key.to_string()).or_default();
+        *part += 1;
+        let path = self.layout.part_path(
+            &self.root,
+            T::TABLE,
+            self.year,
+            self.month,
+            &game_type,
+            *part,
+            self.format,
+        );
+        if let Some(dir) = Path::new(&path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut df = T::to_dataframe(&rows)?;
+        if self.layout == OutputLayout::Hive && df.column("game_type").is_ok() {
+            df = df.drop("game_type")?;
+        }
+        self.format.write_dataframe(&mut df, &path)?;
+        Ok(WrittenPart {
+            table: T::TABLE.to_string(),
+            path,
+            rows: rows.len(),
+        })
+    }
+
+    /// The number of rows waiting in the fullest partition's buffer.
+    pub fn buffered(&self) -> usize {
+        self.buffers
+            .values()
+            .map(|(_, rows)| rows.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Write out every partition's buffered rows, leaving the writer empty but usable.
+    pub fn flush_all(&mut self) -> Result<Vec<WrittenPart>> {
+        let keys: Vec<String> = self.buffers.keys().cloned().collect();
+        keys.iter().map(|key| self.flush(key)).collect()
+    }
+
+    /// The number of files written so far per partition, to resume numbering from with
+    /// [`with_parts_written`](Self::with_parts_written).
+    pub fn parts_written(&self) -> &HashMap<String, usize> {
+        &self.parts
+    }
+
+    /// Continue the part numbering of an earlier, interrupted writer.
+    pub fn with_parts_written(mut self, parts: HashMap<String, usize>) -> Self {
+        self.parts = parts;
+        self
+    }
+
+    /// Write out every partition's remaining rows.
+    pub fn finish(mut self) -> Result<Vec<WrittenPart>> {
+        self.flush_all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataframe::game_schema;
+    use crate::movetext::parse_movetext;
+    use crate::parquet::read_games_parquet;
+    use crate::test_game;
+
+    fn game(game_id: &str, game_type: GameType) -> ChessGame {
+        let mut game = test_game(game_id);
+        game.game_type = game_type;
+        game
+    }
+
+    #[test]
+    fn test_part_path() {
+        assert_eq!(
+            OutputLayout::Flat.part_path(
+                "lichess_data",
+                "games",
+                2016,
+                3,
+                &GameType::Blitz,
+                7,
+                OutputFormat::Csv
+            ),
+            "lichess_data/2016/03/2016-03__007.csv"
+        );
+        assert_eq!(
+            OutputLayout::Hive.part_path(
+                "lichess_data",
+                "games",
+                2016,
+                3,
+                &GameType::Blitz,
+                7,
+                OutputFormat::default()
+            ),
+            "lichess_data/games/year=2016/month=03/game_type=Blitz/part-007.parquet"
+        );
+        assert_eq!(
+            OutputLayout::Hive.month_path("lichess_data", "players", 2016, 3, OutputFormat::Csv),
+            "lichess_data/players/year=2016/month=03/part-001.csv"
+        );
+        assert_eq!(OutputLayout::from_str("Hive"), Ok(OutputLayout::Hive));
+    }
+
+    #[test]
+    fn test_hive_writer_partitions_by_game_type() {
+        let root = std::env::temp_dir().join(format!("chess_rs_hive_{}", std::process::id()));
+        let root = root.to_str().unwrap();
+        let mut writer = GameWriter::new(
+            root,
+            2016,
+            3,
+            OutputLayout::Hive,
+            OutputFormat::default(),
+            2,
+        );
+
+        let mut written = Vec::new();
+        for (id, game_type) in [
+            ("a", GameType::Blitz),
+            ("b", GameType::Bullet),
+            ("c", GameType::Blitz),
+            ("d", GameType::Blitz),
+        ] {
+            written.extend(writer.push(game(id, game_type)).unwrap());
+        }
+        written.extend(writer.finish().unwrap());
+
+        let summary: Vec<(String, usize)> = written
+            .iter()
+            .map(|p| (p.path.trim_start_matches(root).to_string(), p.rows))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "/games/year=2016/month=03/game_type=Blitz/part-001.parquet".to_string(),
+                    2
+                ),
+                (
+                    "/games/year=2016/month=03/game_type=Blitz/part-002.parquet".to_string(),
+                    1
+                ),
+                (
+                    "/games/year=2016/month=03/game_type=Bullet/part-001.parquet".to_string(),
+                    1
+                ),
+            ]
+        );
+
+        let df = read_games_parquet(&written[0].path).unwrap();
+        assert!(df.column("game_type").is_err());
+        assert_eq!(df.height(), 2);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_output_formats_share_columns() {
+        let mut games = vec![game("a", GameType::Blitz), game("b", GameType::Rapid)];
+        games[0].moves =
+            parse_movetext("1. e4 { [%eval 0.2] [%clk 0:03:00] } 1... c5 { [%clk 0:02:58] }");
+        let mut df = games_to_dataframe(&games).unwrap();
+        let names: Vec<String> = game_schema()
+            .iter_names()
+            .map(|name| name.to_string())
+            .collect();
+        let dir = std::env::temp_dir();
+
+        for format in [
+            OutputFormat::ArrowIpc,
+            OutputFormat::Csv,
+            OutputFormat::Ndjson,
+        ] {
+            let path = dir.join(format!(
+                "chess_rs_games_{}.{}",
+                std::process::id(),
+                format.extension()
+            ));
+            let path = path.to_str().unwrap();
+            format.write_dataframe(&mut df, path).unwrap();
+            let contents = fs::read(path).unwrap();
+
+            match format {
+                OutputFormat::ArrowIpc => {
+                    let read = IpcReader::new(fs::File::open(path).unwrap())
+                        .finish()
+                        .unwrap();
+                    assert_eq!(read.schema(), game_schema());
+                }
+                OutputFormat::Csv => {
+                    let text = String::from_utf8(contents).unwrap();
+                    let mut lines = text.lines();
+                    assert_eq!(lines.next().unwrap(), names.join(","));
+                    let first = lines.next().unwrap();
+                    assert!(first.contains(",e4 c5,"));
+                    assert!(first.contains(",180000 178000,"));
+                    assert!(first.contains(",20 null"));
+                }
+                OutputFormat::Ndjson => {
+                    let text = String::from_utf8(contents).unwrap();
+                    assert_eq!(text.lines().count(), 2);
+                    let first = text.lines().next().unwrap();
+                    for name in &names {
+                        assert!(first.contains(&format!("\"{}\":", name)), "{}", name);
+                    }
+                    assert!(first.contains("\"game_type\":\"Blitz\""));
+                }
+                OutputFormat::Parquet(_) => unreachable!(),
+            }
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_csv_keeps_sparse_lists_aligned() {
+        let mut sparse = game("a", GameType::Blitz);
+        sparse.moves =
+            parse_movetext("1. e4 e5 { [%eval 0.3] } 2. Nf3 Nc6 { [%eval -0.5] } 3. Bb5");
+        let mut df = games_to_dataframe(&[sparse]).unwrap();
+        let path = std::env::temp_dir().join(format!("chess_rs_sparse_{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        OutputFormat::Csv.write_dataframe(&mut df, path).unwrap();
+
+        let read = CsvReader::from_path(path).unwrap().finish().unwrap();
+        let tokens = |name: &str| -> Vec<String> {
+            let column = read.column(name).unwrap().cast(&DataType::Utf8).unwrap();
+            let value = column.utf8().unwrap().get(0).unwrap().to_string();
+            value.split(' ').map(str::to_string).collect()
+        };
+        assert_eq!(tokens("moves_san"), ["e4", "e5", "Nf3", "Nc6", "Bb5"]);
+        assert_eq!(tokens("evals"), ["null", "30", "null", "-50", "null"]);
+        assert_eq!(tokens("clocks"), ["null"; 5]);
+        fs::remove_file(path).unwrap();
+    }
+}
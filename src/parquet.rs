@@ -0,0 +1,262 @@
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::str::FromStr;
+
+use anyhow::Result;
+use derive_builder::Builder;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::atomic::write_atomically;
+use crate::dataframe::{dataframe_to_games, games_to_dataframe};
+use crate::ChessGame;
+
+/// Compression codec for Parquet data pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ParquetCodec {
+    Uncompressed,
+    Snappy,
+    Lz4,
+    /// Zstandard, at the given level (1-22) or the library default.
+    Zstd(Option<i32>),
+}
+
+impl FromStr for ParquetCodec {
+    type Err = ();
+    /// Parse a codec name, optionally followed by a level, e.g. `snappy` or `zstd:9`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, level) = match s.split_once(':') {
+            Some((name, level)) => (name, Some(level.parse::<i32>().map_err(|_| ())?)),
+            None => (s, None),
+        };
+        match (name.to_lowercase().as_str(), level) {
+            ("uncompressed" | "none", None) => Ok(Self::Uncompressed),
+            ("snappy", None) => Ok(Self::Snappy),
+            ("lz4", None) => Ok(Self::Lz4),
+            ("zstd", None) => Ok(Self::Zstd(None)),
+            ("zstd", Some(level)) if (1..=22).contains(&level) => Ok(Self::Zstd(Some(level))),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for ParquetCodec {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Uncompressed => write!(f, "uncompressed"),
+            Self::Snappy => write!(f, "snappy"),
+            Self::Lz4 => write!(f, "lz4"),
+            Self::Zstd(None) => write!(f, "zstd"),
+            Self::Zstd(Some(level)) => write!(f, "zstd:{}", level),
+        }
+    }
+}
+
+/// Settings for writing Parquet files.
+#[derive(Debug, Clone, Copy, Builder, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParquetWriterConfig {
+    #[builder(default = "ParquetCodec::Zstd(None)")]
+    pub codec: ParquetCodec,
+    /// Rows per row group, or `None` for a single row group per file. Smaller row groups
+    /// let readers skip more data using the statistics, at some cost in compression.
+    #[builder(default = "Some(50_000)")]
+    pub row_group_size: Option<usize>,
+    /// Dictionary-encode the categorical columns (game type, winner, termination, ...).
+    /// When off they are written as plain strings.
+    #[builder(default = "true")]
+    pub dictionary: bool,
+    /// Write min/max/null-count statistics, which readers use to push down filters.
+    #[builder(default = "true")]
+    pub statistics: bool,
+}
+
+impl ParquetWriterConfig {
+    pub fn builder() -> ParquetWriterConfigBuilder {
+        ParquetWriterConfigBuilder::default()
+    }
+
+    fn compression(&self) -> Result<ParquetCompression> {
+        Ok(match self.codec {
+            ParquetCodec::Uncompressed => ParquetCompression::Uncompressed,
+            ParquetCodec::Snappy => ParquetCompression::Snappy,
+            ParquetCodec::Lz4 => ParquetCompression::Lz4Raw,
+            ParquetCodec::Zstd(level) => {
+                ParquetCompression::Zstd(level.map(ZstdLevel::try_new).transpose()?)
+            }
+        })
+    }
+}
+
+impl Default for ParquetWriterConfig {
+    fn default() -> Self {
+        Self::builder().build().expect("all fields have defaults")
+    }
+}
+
+/// Write a slice of [`ChessGame`] objects to a Parquet file using Polars.
+///
+/// # Arguments
+///
+/// * `games` - A slice of `ChessGame` objects.
+/// * `output_path` - The path for the output Parquet file.
+/// * `config` - Compression and layout settings.
+pub fn write_games_to_parquet(
+    games: &[ChessGame],
+    output_path: &str,
+    config: &ParquetWriterConfig,
+) -> Result<()> {
+    let mut df = games_to_dataframe(games)?;
+    write_dataframe_to_parquet(&mut df, output_path, config)
+}
+
+/// Write a DataFrame to a Parquet file, which only appears once complete.
+pub fn write_dataframe_to_parquet(
+    df: &mut DataFrame,
+    output_path: &str,
+    config: &ParquetWriterConfig,
+) -> Result<()> {
+    if !config.dictionary {
+        for name in df.get_column_names_owned() {
+            if matches!(df.column(&name)?.dtype(), DataType::Categorical(_)) {
+                df.try_apply(&name, |s| s.cast(&DataType::Utf8))?;
+            }
+        }
+    }
+    let compression = config.compression()?;
+    write_atomically(output_path, |file| {
+        ParquetWriter::new(file)
+            .with_compression(compression)
+            .with_row_group_size(config.row_group_size)
+            .with_statistics(config.statistics)
+            .finish(df)?;
+        Ok(())
+    })
+}
+
+/// Read a Parquet file of games, as written by [`write_games_to_parquet`], into a DataFrame.
+pub fn read_games_parquet(path: &str) -> Result<DataFrame> {
+    let file = fs::File::open(path)?;
+    Ok(ParquetReader::new(file).finish()?)
+}
+
+/// Read a Parquet file of games back into [`ChessGame`] objects.
+pub fn read_games_from_parquet(path: &str) -> Result<Vec<ChessGame>> {
+    dataframe_to_games(&read_games_parquet(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataframe::game_schema;
+    use crate::movetext::parse_movetext;
+    use crate::{test_game, Winner};
+    use chrono::{NaiveDate, NaiveTime};
+
+    fn game(game_id: &str, winner: Option<Winner>, date: Option<NaiveDate>) -> ChessGame {
+        let mut game = test_game(game_id);
+        game.white_player_elo = 2105;
+        game.winner = winner;
+        game.date = date;
+        game.time = NaiveTime::from_hms_opt(22, 0, 11);
+        game
+    }
+
+    #[test]
+    fn test_write_and_read_back_parquet() {
+        let mut games = vec![
+            game(
+                "a",
+                Some(Winner::White),
+                NaiveDate::from_ymd_opt(2016, 3, 1),
+            ),
+            game("b", None, None),
+        ];
+        games[0].moves = parse_movetext(
+            "1. e4 { [%eval 0.2] [%clk 0:03:00] } 1... c5 { [%eval 0.3] [%clk 0:02:58] }",
+        );
+        games[0].moves[0].uci = Some("e2e4".to_string());
+        games[0].moves[1].uci = Some("c7c5".to_string());
+        let path =
+            std::env::temp_dir().join(format!("chess_rs_games_{}.parquet", std::process::id()));
+        let path = path.to_str().unwrap();
+        write_games_to_parquet(&games, path, &ParquetWriterConfig::default()).unwrap();
+        let df = read_games_parquet(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(df.schema(), game_schema());
+        assert_eq!(df.height(), 2);
+
+        let winner = df.column("winner").unwrap().cast(&DataType::Utf8).unwrap();
+        assert_eq!(winner.utf8().unwrap().get(0), Some("White"));
+        assert_eq!(winner.utf8().unwrap().get(1), None);
+
+        let date = df.column("date").unwrap().date().unwrap();
+        assert_eq!(
+            date.as_date_iter().next().unwrap(),
+            NaiveDate::from_ymd_opt(2016, 3, 1)
+        );
+        assert_eq!(date.get(1), None);
+
+        let time = df.column("time").unwrap().time().unwrap();
+        assert_eq!(
+            time.as_time_iter().next().unwrap(),
+            NaiveTime::from_hms_opt(22, 0, 11)
+        );
+
+        let elo = df.column("white_player_elo").unwrap().u32().unwrap();
+        assert_eq!(elo.get(0), Some(2105));
+
+        let sans = df
+            .column("moves_san")
+            .unwrap()
+            .list()
+            .unwrap()
+            .get(0)
+            .unwrap();
+        assert_eq!(sans.utf8().unwrap().get(1), Some("c5"));
+        let clocks = df.column("clocks").unwrap().list().unwrap().get(0).unwrap();
+        assert_eq!(clocks.dtype(), &DataType::Duration(TimeUnit::Milliseconds));
+
+        assert_eq!(dataframe_to_games(&df).unwrap(), games);
+    }
+
+    #[test]
+    fn test_parse_codec() {
+        assert_eq!(
+            ParquetCodec::from_str("zstd:9"),
+            Ok(ParquetCodec::Zstd(Some(9)))
+        );
+        assert_eq!(ParquetCodec::from_str("Snappy"), Ok(ParquetCodec::Snappy));
+        assert_eq!(ParquetCodec::from_str("zstd:99"), Err(()));
+        assert_eq!(ParquetCodec::from_str("lz4:3"), Err(()));
+        assert_eq!(ParquetCodec::Zstd(Some(9)).to_string(), "zstd:9");
+    }
+
+    #[test]
+    fn test_writer_config() {
+        let games: Vec<ChessGame> = (0..10).map(|i| game(&i.to_string(), None, None)).collect();
+        let path =
+            std::env::temp_dir().join(format!("chess_rs_config_{}.parquet", std::process::id()));
+        let path = path.to_str().unwrap();
+        for codec in [
+            ParquetCodec::Uncompressed,
+            ParquetCodec::Snappy,
+            ParquetCodec::Lz4,
+            ParquetCodec::Zstd(Some(19)),
+        ] {
+            let config = ParquetWriterConfig::builder()
+                .codec(codec)
+                .row_group_size(Some(3))
+                .dictionary(false)
+                .statistics(false)
+                .build()
+                .unwrap();
+            write_games_to_parquet(&games, path, &config).unwrap();
+            let df = read_games_parquet(path).unwrap();
+            assert_eq!(df.height(), 10);
+            assert_eq!(df.column("game_type").unwrap().dtype(), &DataType::Utf8);
+            assert_eq!(dataframe_to_games(&df).unwrap(), games);
+        }
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,291 @@
+use std::io::{self, BufRead};
+use std::sync::mpsc::sync_channel;
+use std::thread;
+
+use anyhow::{anyhow, Result};
+use derive_builder::Builder;
+use rayon::prelude::*;
+
+/// Sizes of the batches and queues of [`run_pipeline`], which bound its memory use.
+#[derive(Debug, Clone, Builder, PartialEq)]
+pub struct PipelineConfig {
+    /// Games handed to the parser workers at a time.
+    #[builder(default = "1_000")]
+    pub batch_size: usize,
+    /// Batches that may wait between the reader, the parsers and the writer.
+    #[builder(default = "4")]
+    pub channel_capacity: usize,
+    /// Games per output file.
+    #[builder(default = "100_000")]
+    pub games_per_file: usize,
+    /// Rows (plies) per positions table file.
+    #[builder(default = "5_000_000")]
+    pub positions_per_file: usize,
+    /// Rows (plies) per ply-features table file.
+    #[builder(default = "5_000_000")]
+    pub ply_features_per_file: usize,
+    /// Rows per rating-history table file.
+    #[builder(default = "1_000_000")]
+    pub rating_changes_per_file: usize,
+}
+
+impl PipelineConfig {
+    pub fn builder() -> PipelineConfigBuilder {
+        PipelineConfigBuilder::default()
+    }
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self::builder().build().expect("all fields have defaults")
+    }
+}
+
+/// Counts of what went through a [`run_pipeline`] run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PipelineStats {
+    /// PGN game blocks read from the input.
+    pub games_read: usize,
+    /// Games that parsed and were handed to the sink.
+    pub games_parsed: usize,
+    /// Bytes of input up to the end of the last game read, so a later run can seek past
+    /// the games already handled.
+    pub bytes_read: u64,
+}
+
+/// An iterator over the PGN text of each game in a reader, one game at a time.
+///
+/// A game starts at every line beginning with `[Event `.
+pub struct PgnGames<R> {
+    reader: R,
+    line: String,
+    current: String,
+    /// Bytes read from the reader so far.
+    read: u64,
+    /// Bytes up to the end of the last game returned.
+    offset: u64,
+}
+
+impl<R: BufRead> PgnGames<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+            current: String::new(),
+            read: 0,
+            offset: 0,
+        }
+    }
+
+    /// The byte offset in the input just past the last game returned.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+impl<R: BufRead> Iterator for PgnGames<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            let n = match self.reader.read_line(&mut self.line) {
+                Ok(n) => n,
+                Err(e) => return Some(Err(e)),
+            };
+            if n == 0 {
+                break;
+            }
+            self.read += n as u64;
+            let line = self.line.trim_end_matches(['\n', '\r']);
+            let starts_game = line.starts_with("[Event ");
+            let game = if starts_game && !self.current.is_empty() {
+                // The game ended where this line began.
+                self.offset = self.read - n as u64;
+                Some(std::mem::take(&mut self.current))
+            } else {
+                None
+            };
+            if starts_game || !self.current.is_empty() {
+                self.current.push_str(line);
+                self.current.push('\n');
+            }
+            if game.is_some() {
+                return game.map(Ok);
+            }
+        }
+        self.offset = self.read;
+        if self.current.is_empty() {
+            None
+        } else {
+            Some(Ok(std::mem::take(&mut self.current)))
+        }
+    }
+}
+
+/// Parse the PGN games of a reader and hand them to `sink` in batches, keeping only a
+/// bounded number of games in memory however long the input is.
+///
+/// A reader thread splits the input into batches of games, a parser thread parses each
+/// batch in parallel with Rayon, and `sink` is called on the calling thread with the
+/// parsed games of each batch, in input order, along with the totals so far. Both queues
+/// between them are bounded, so a slow sink holds back reading.
+///
+/// # Arguments
+///
+/// * `reader` - The PGN input.
+/// * `parse` - Parses the text of a single game, or returns `None` to skip it. It can return
+///   more than the [`ChessGame`](crate::ChessGame) itself, e.g. rows collected while replaying it.
+/// * `config` - Batch and queue sizes.
+/// * `sink` - Receives the parsed games, e.g. to write them out, and the counts up to and
+///   including them.
+pub fn run_pipeline<R, T, P, F>(
+    reader: R,
+    parse: P,
+    config: &PipelineConfig,
+    mut sink: F,
+) -> Result<PipelineStats>
+where
+    R: BufRead + Send,
+    T: Send,
+    P: Fn(&str) -> Option<T> + Sync,
+    F: FnMut(Vec<T>, &PipelineStats) -> Result<()>,
+{
+    // Each batch travels with the input offset just past its last game.
+    let (text_tx, text_rx) = sync_channel::<(Vec<String>, u64)>(config.channel_capacity);
+    let (game_tx, game_rx) = sync_channel::<(usize, Vec<T>, u64)>(config.channel_capacity);
+    let batch_size = config.batch_size.max(1);
+
+    thread::scope(|scope| {
+        let reader = scope.spawn(move || -> io::Result<()> {
+            let mut games = PgnGames::new(reader);
+            let mut batch = Vec::with_capacity(batch_size);
+            while let Some(game) = games.next() {
+                batch.push(game?);
+                if batch.len() == batch_size {
+                    let full = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
+                    if text_tx.send((full, games.offset())).is_err() {
+                        // The sink failed; its error is reported below.
+                        return Ok(());
+                    }
+                }
+            }
+            if !batch.is_empty() {
+                let _ = text_tx.send((batch, games.offset()));
+            }
+            Ok(())
+        });
+
+        let parse = &parse;
+        scope.spawn(move || {
+            for (texts, offset) in text_rx {
+                let games: Vec<T> = texts.par_iter().filter_map(|text| parse(text)).collect();
+                if game_tx.send((texts.len(), games, offset)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let mut stats = PipelineStats::default();
+        for (read, games, offset) in game_rx {
+            stats.games_read += read;
+            stats.games_parsed += games.len();
+            stats.bytes_read = offset;
+            sink(games, &stats)?;
+        }
+        reader
+            .join()
+            .map_err(|_| anyhow!("PGN reader thread panicked"))??;
+        Ok(stats)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_game, ChessGame};
+
+    fn pgn(game_id: &str) -> String {
+        format!(
+            "[Event \"Rated Blitz game\"]\n[Site \"https://lichess.org/{}\"]\n\n1. e4 e5 1-0\n\n",
+            game_id
+        )
+    }
+
+    /// Parses just the game id from the `Site` header, skipping games without one.
+    fn parse_site(text: &str) -> Option<ChessGame> {
+        let url = text
+            .lines()
+            .find_map(|line| line.strip_prefix("[Site \""))?;
+        let url = url.trim_end_matches("\"]");
+        Some(test_game(url.rsplit('/').next()?))
+    }
+
+    #[test]
+    fn test_pgn_games_splits_on_event() {
+        let input = format!("junk before\n{}{}", pgn("a"), pgn("b"));
+        let games: Vec<String> = PgnGames::new(input.as_bytes())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(games, vec![pgn("a"), pgn("b")]);
+    }
+
+    #[test]
+    fn test_pgn_games_offsets_resume_after_a_game() {
+        let input = format!("{}{}{}", pgn("a"), pgn("b").replace('\n', "\r\n"), pgn("c"));
+        let mut games = PgnGames::new(input.as_bytes());
+        games.next().unwrap().unwrap();
+        assert_eq!(games.offset(), pgn("a").len() as u64);
+        assert_eq!(games.next().unwrap().unwrap(), pgn("b"));
+
+        let resumed: Vec<String> = PgnGames::new(&input.as_bytes()[games.offset() as usize..])
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(resumed, vec![pgn("c")]);
+    }
+
+    #[test]
+    fn test_pipeline_preserves_order_in_batches() {
+        let mut input: String = (0..25).map(|i| pgn(&format!("g{:02}", i))).collect();
+        input.push_str("[Event \"Rated Blitz game\"]\n\n1. e4 *\n");
+        let config = PipelineConfig::builder()
+            .batch_size(4)
+            .channel_capacity(1)
+            .build()
+            .unwrap();
+
+        let mut batches = Vec::new();
+        let stats = run_pipeline(input.as_bytes(), parse_site, &config, |games, _| {
+            batches.push(games.iter().map(|g| g.game_id.clone()).collect::<Vec<_>>());
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(
+            stats,
+            PipelineStats {
+                games_read: 26,
+                games_parsed: 25,
+                bytes_read: input.len() as u64,
+            }
+        );
+        assert!(batches.iter().all(|batch| batch.len() <= 4));
+        let ids: Vec<String> = batches.concat();
+        let expected: Vec<String> = (0..25).map(|i| format!("g{:02}", i)).collect();
+        assert_eq!(ids, expected);
+    }
+
+    #[test]
+    fn test_pipeline_stops_on_sink_error() {
+        let input: String = (0..100).map(|i| pgn(&i.to_string())).collect();
+        let config = PipelineConfig::builder()
+            .batch_size(1)
+            .channel_capacity(1)
+            .build()
+            .unwrap();
+        let result = run_pipeline(input.as_bytes(), parse_site, &config, |_, _| {
+            Err(anyhow!("disk full"))
+        });
+        assert_eq!(result.unwrap_err().to_string(), "disk full");
+    }
+}
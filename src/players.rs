@@ -0,0 +1,452 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::output::{TableRow, TableWriter};
+use crate::{ChessGame, GameType};
+
+/// A player's rating change in one rated game, from the Elo and rating-diff headers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RatingChange {
+    pub player: String,
+    pub game_id: String,
+    /// When the game started, if the headers say.
+    pub timestamp: Option<NaiveDateTime>,
+    /// The speed the rating belongs to.
+    pub game_type: GameType,
+    pub rating_before: u32,
+    /// Missing when the game has no rating-diff header.
+    pub rating_after: Option<u32>,
+}
+
+/// When a game started: its date and time, or midnight if only the date is known.
+fn game_timestamp(game: &ChessGame) -> Option<NaiveDateTime> {
+    game.date
+        .map(|date| date.and_time(game.time.unwrap_or(NaiveTime::MIN)))
+}
+
+/// The rating changes of both players in a game, White first. Unrated games have none.
+pub fn rating_changes(game: &ChessGame) -> Vec<RatingChange> {
+    if !game.rated {
+        return Vec::new();
+    }
+    let change = |player: &str, elo: u32, diff: Option<i32>| RatingChange {
+        player: player.to_string(),
+        game_id: game.game_id.clone(),
+        timestamp: game_timestamp(game),
+        game_type: game.game_type.clone(),
+        rating_before: elo,
+        rating_after: diff.map(|diff| (elo as i64 + diff as i64).max(0) as u32),
+    };
+    vec![
+        change(
+            &game.white_player_name,
+            game.white_player_elo,
+            game.white_rating_change,
+        ),
+        change(
+            &game.black_player_name,
+            game.black_player_elo,
+            game.black_rating_change,
+        ),
+    ]
+}
+
+/// A player's games and ratings at one speed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SpeedStats {
+    pub games: u32,
+    /// Rating after the most recent game.
+    pub latest_elo: u32,
+    pub peak_elo: u32,
+    latest_at: Option<NaiveDateTime>,
+}
+
+impl SpeedStats {
+    fn add(&mut self, elo: u32, at: Option<NaiveDateTime>) {
+        self.games += 1;
+        self.peak_elo = self.peak_elo.max(elo);
+        // Games without a timestamp count as the latest only if nothing else is known.
+        if self.games == 1 || at >= self.latest_at {
+            self.latest_elo = elo;
+            self.latest_at = at;
+        }
+    }
+
+    fn merge(&mut self, other: &SpeedStats) {
+        self.games += other.games;
+        self.peak_elo = self.peak_elo.max(other.peak_elo);
+        if other.latest_at >= self.latest_at {
+            self.latest_elo = other.latest_elo;
+            self.latest_at = other.latest_at;
+        }
+    }
+}
+
+/// One row of the players table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Player {
+    pub name: String,
+    /// The most recently seen title, e.g. `GM` or `BOT`.
+    pub title: Option<String>,
+    pub first_seen: Option<NaiveDate>,
+    pub last_seen: Option<NaiveDate>,
+    pub speeds: BTreeMap<GameType, SpeedStats>,
+    title_at: Option<NaiveDateTime>,
+}
+
+impl Player {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            title: None,
+            first_seen: None,
+            last_seen: None,
+            speeds: BTreeMap::new(),
+            title_at: None,
+        }
+    }
+
+    fn see_title(&mut self, title: &Option<String>, at: Option<NaiveDateTime>) {
+        if title.is_some() && (self.title.is_none() || at >= self.title_at) {
+            self.title = title.clone();
+            self.title_at = at;
+        }
+    }
+
+    fn see(&mut self, first: Option<NaiveDate>, last: Option<NaiveDate>) {
+        self.first_seen = match (self.first_seen, first) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.last_seen = self.last_seen.max(last);
+    }
+}
+
+/// Builds the players table from games, one player at a time.
+///
+/// Serializable, so a partial index can be saved and [merged](Self::merge) later.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PlayerIndex {
+    players: HashMap<String, Player>,
+}
+
+impl PlayerIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a game for both of its players.
+    pub fn add_game(&mut self, game: &ChessGame) {
+        let at = game_timestamp(game);
+        let sides = [
+            (
+                &game.white_player_name,
+                &game.white_title,
+                game.white_player_elo,
+                game.white_rating_change,
+            ),
+            (
+                &game.black_player_name,
+                &game.black_title,
+                game.black_player_elo,
+                game.black_rating_change,
+            ),
+        ];
+        for (name, title, elo, change) in sides {
+            let player = self
+                .players
+                .entry(name.clone())
+                .or_insert_with(|| Player::new(name));
+            player.see(game.date, game.date);
+            player.see_title(title, at);
+            let after = change.map_or(elo, |diff| (elo as i64 + diff as i64).max(0) as u32);
+            let stats = player.speeds.entry(game.game_type.clone()).or_default();
+            stats.peak_elo = stats.peak_elo.max(elo);
+            stats.add(after, at);
+        }
+    }
+
+    /// Combine the players seen by another index, e.g. for another month.
+    pub fn merge(&mut self, other: PlayerIndex) {
+        for (name, other) in other.players {
+            let Some(player) = self.players.get_mut(&name) else {
+                self.players.insert(name, other);
+                continue;
+            };
+            player.see_title(&other.title, other.title_at);
+            player.see(other.first_seen, other.last_seen);
+            for (game_type, stats) in &other.speeds {
+                player
+                    .speeds
+                    .entry(game_type.clone())
+                    .or_default()
+                    .merge(stats);
+            }
+        }
+    }
+
+    /// The players, sorted by name.
+    pub fn into_players(self) -> Vec<Player> {
+        let mut players: Vec<Player> = self.players.into_values().collect();
+        players.sort_by(|a, b| a.name.cmp(&b.name));
+        players
+    }
+}
+
+/// The columns of the players table: name, title, first and last seen date, then the
+/// number of games, latest Elo and peak Elo for each speed, e.g. `blitz_games`,
+/// `blitz_latest_elo` and `blitz_peak_elo`.
+pub fn player_schema() -> Schema {
+    let mut fields = vec![
+        Field::new("name", DataType::Utf8),
+        Field::new("title", DataType::Utf8),
+        Field::new("first_seen", DataType::Date),
+        Field::new("last_seen", DataType::Date),
+    ];
+    for game_type in GameType::ALL {
+        let speed = game_type.to_string().to_lowercase();
+        fields.push(Field::new(&format!("{}_games", speed), DataType::UInt32));
+        fields.push(Field::new(
+            &format!("{}_latest_elo", speed),
+            DataType::UInt32,
+        ));
+        fields.push(Field::new(&format!("{}_peak_elo", speed), DataType::UInt32));
+    }
+    Schema::from_iter(fields)
+}
+
+/// Convert players into a DataFrame with the [`player_schema`].
+pub fn players_to_dataframe(players: &[Player]) -> PolarsResult<DataFrame> {
+    let date = |name: &str, f: fn(&Player) -> Option<NaiveDate>| {
+        DateChunked::from_naive_date_options(name, players.iter().map(f)).into_series()
+    };
+    let mut columns = vec![
+        Series::new(
+            "name",
+            players
+                .iter()
+                .map(|p| p.name.as_str())
+                .collect::<Vec<&str>>(),
+        ),
+        Series::new(
+            "title",
+            players
+                .iter()
+                .map(|p| p.title.as_deref())
+                .collect::<Vec<Option<&str>>>(),
+        ),
+        date("first_seen", |p| p.first_seen),
+        date("last_seen", |p| p.last_seen),
+    ];
+    for game_type in GameType::ALL {
+        let speed = game_type.to_string().to_lowercase();
+        let stats: Vec<Option<&SpeedStats>> =
+            players.iter().map(|p| p.speeds.get(&game_type)).collect();
+        columns.push(Series::new(
+            &format!("{}_games", speed),
+            stats
+                .iter()
+                .map(|s| s.map_or(0, |s| s.games))
+                .collect::<Vec<u32>>(),
+        ));
+        columns.push(Series::new(
+            &format!("{}_latest_elo", speed),
+            stats
+                .iter()
+                .map(|s| s.map(|s| s.latest_elo))
+                .collect::<Vec<Option<u32>>>(),
+        ));
+        columns.push(Series::new(
+            &format!("{}_peak_elo", speed),
+            stats
+                .iter()
+                .map(|s| s.map(|s| s.peak_elo))
+                .collect::<Vec<Option<u32>>>(),
+        ));
+    }
+    DataFrame::new(columns)
+}
+
+/// The schema of the rating-history table.
+pub fn rating_history_schema() -> Schema {
+    Schema::from_iter([
+        Field::new("player", DataType::Utf8),
+        Field::new("game_id", DataType::Utf8),
+        Field::new(
+            "timestamp",
+            DataType::Datetime(TimeUnit::Milliseconds, None),
+        ),
+        Field::new("game_type", DataType::Categorical(None)),
+        Field::new("rating_before", DataType::UInt32),
+        Field::new("rating_after", DataType::UInt32),
+    ])
+}
+
+/// Convert rating changes into a DataFrame with the [`rating_history_schema`].
+pub fn rating_changes_to_dataframe(changes: &[RatingChange]) -> PolarsResult<DataFrame> {
+    let utf8 = |name: &str, f: fn(&RatingChange) -> &str| {
+        Series::new(name, changes.iter().map(f).collect::<Vec<&str>>())
+    };
+    let timestamps: Vec<Option<i64>> = changes
+        .iter()
+        .map(|c| c.timestamp.map(|t| t.and_utc().timestamp_millis()))
+        .collect();
+    DataFrame::new(vec![
+        utf8("player", |c| &c.player),
+        utf8("game_id", |c| &c.game_id),
+        Series::new("timestamp", timestamps)
+            .cast(&DataType::Datetime(TimeUnit::Milliseconds, None))?,
+        Series::new(
+            "game_type",
+            changes
+                .iter()
+                .map(|c| c.game_type.to_string())
+                .collect::<Vec<String>>(),
+        )
+        .cast(&DataType::Categorical(None))?,
+        Series::new(
+            "rating_before",
+            changes
+                .iter()
+                .map(|c| c.rating_before)
+                .collect::<Vec<u32>>(),
+        ),
+        Series::new(
+            "rating_after",
+            changes
+                .iter()
+                .map(|c| c.rating_after)
+                .collect::<Vec<Option<u32>>>(),
+        ),
+    ])
+}
+
+impl TableRow for RatingChange {
+    const TABLE: &'static str = "rating_history";
+
+    fn game_type(&self) -> &GameType {
+        &self.game_type
+    }
+
+    fn to_dataframe(rows: &[Self]) -> PolarsResult<DataFrame> {
+        rating_changes_to_dataframe(rows)
+    }
+}
+
+/// Writes the rating-history table.
+pub type RatingHistoryWriter = TableWriter<RatingChange>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_game;
+
+    fn game(
+        white: (&str, u32, i32),
+        black: (&str, u32, i32),
+        game_type: GameType,
+        day: u32,
+    ) -> ChessGame {
+        let mut game = test_game(&format!("g{}", day));
+        game.game_type = game_type;
+        game.white_player_name = white.0.to_string();
+        game.white_player_elo = white.1;
+        game.white_rating_change = Some(white.2);
+        game.black_player_name = black.0.to_string();
+        game.black_player_elo = black.1;
+        game.black_rating_change = Some(black.2);
+        game.date = NaiveDate::from_ymd_opt(2016, 3, day);
+        game.time = NaiveTime::from_hms_opt(12, 0, 0);
+        game
+    }
+
+    #[test]
+    fn test_rating_changes() {
+        let changes = rating_changes(&game(
+            ("alice", 1500, 8),
+            ("bob", 1600, -8),
+            GameType::Blitz,
+            1,
+        ));
+        assert_eq!(changes[0].rating_after, Some(1508));
+        assert_eq!(changes[1].player, "bob");
+        assert_eq!(changes[1].rating_after, Some(1592));
+        assert_eq!(
+            changes[1].timestamp,
+            NaiveDate::from_ymd_opt(2016, 3, 1)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+        );
+
+        let unrated = ChessGame {
+            rated: false,
+            ..game(("alice", 1500, 8), ("bob", 1600, -8), GameType::Blitz, 1)
+        };
+        assert!(rating_changes(&unrated).is_empty());
+    }
+
+    #[test]
+    fn test_player_index() {
+        // Games are seen out of order, as they would be across months.
+        let mut index = PlayerIndex::new();
+        index.add_game(&game(
+            ("alice", 1520, -10),
+            ("bob", 1600, 10),
+            GameType::Blitz,
+            5,
+        ));
+        let mut other = PlayerIndex::new();
+        let mut titled = game(("alice", 1500, 20), ("carol", 2000, -2), GameType::Blitz, 2);
+        titled.white_title = Some("WFM".to_string());
+        other.add_game(&titled);
+        other.add_game(&game(
+            ("bob", 1400, 5),
+            ("alice", 1300, -5),
+            GameType::Bullet,
+            3,
+        ));
+        index.merge(other);
+
+        let players = index.into_players();
+        let names: Vec<&str> = players.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["alice", "bob", "carol"]);
+        let alice = &players[0];
+        assert_eq!(alice.title.as_deref(), Some("WFM"));
+        assert_eq!(alice.first_seen, NaiveDate::from_ymd_opt(2016, 3, 2));
+        assert_eq!(alice.last_seen, NaiveDate::from_ymd_opt(2016, 3, 5));
+        let blitz = &alice.speeds[&GameType::Blitz];
+        assert_eq!(
+            (blitz.games, blitz.latest_elo, blitz.peak_elo),
+            (2, 1510, 1520)
+        );
+        assert_eq!(alice.speeds[&GameType::Bullet].games, 1);
+
+        let df = players_to_dataframe(&players).unwrap();
+        assert_eq!(df.schema(), player_schema());
+        let bullet = df.column("bullet_latest_elo").unwrap().u32().unwrap();
+        assert_eq!(bullet.get(2), None);
+        assert_eq!(
+            df.column("blitz_games").unwrap().u32().unwrap().get(1),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_rating_history_dataframe() {
+        let changes = rating_changes(&game(
+            ("alice", 1500, 8),
+            ("bob", 1600, -8),
+            GameType::Rapid,
+            1,
+        ));
+        let df = rating_changes_to_dataframe(&changes).unwrap();
+        assert_eq!(df.schema(), rating_history_schema());
+        assert_eq!(
+            df.column("rating_after").unwrap().u32().unwrap().get(1),
+            Some(1592)
+        );
+    }
+}
@@ -0,0 +1,427 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufWriter, Write};
+
+use anyhow::{anyhow, Result};
+use derive_builder::Builder;
+use shakmaty::zobrist::{Zobrist64, ZobristHash};
+use shakmaty::{Chess, Color, EnPassantMode, Move, Position, Role};
+
+use crate::atomic::write_atomically;
+use crate::replay::{replay, Ply, PlyVisitor};
+use crate::{ChessGame, GameType, TerminationType, Winner};
+
+/// Size in bytes of one entry in a Polyglot `.bin` file.
+pub const ENTRY_SIZE: usize = 16;
+
+/// One entry of a Polyglot opening book: a move that can be played in a position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolyglotEntry {
+    /// Polyglot Zobrist hash of the position.
+    pub key: u64,
+    /// The move, packed as `to | from << 6 | promotion << 12`.
+    pub mv: u16,
+    /// Relative weight of the move among all moves for the same key.
+    pub weight: u16,
+    pub learn: u32,
+}
+
+impl PolyglotEntry {
+    /// Encode the entry in the big-endian on-disk format.
+    pub fn to_bytes(&self) -> [u8; ENTRY_SIZE] {
+        let mut bytes = [0; ENTRY_SIZE];
+        bytes[0..8].copy_from_slice(&self.key.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.mv.to_be_bytes());
+        bytes[10..12].copy_from_slice(&self.weight.to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.learn.to_be_bytes());
+        bytes
+    }
+
+    /// Decode an entry from the big-endian on-disk format.
+    pub fn from_bytes(bytes: &[u8; ENTRY_SIZE]) -> Self {
+        Self {
+            key: u64::from_be_bytes(bytes[0..8].try_into().unwrap()),
+            mv: u16::from_be_bytes(bytes[8..10].try_into().unwrap()),
+            weight: u16::from_be_bytes(bytes[10..12].try_into().unwrap()),
+            learn: u32::from_be_bytes(bytes[12..16].try_into().unwrap()),
+        }
+    }
+}
+
+/// The Polyglot hash of a position.
+pub fn polyglot_key(position: &Chess) -> u64 {
+    position.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0
+}
+
+/// Pack a move in Polyglot format. Castling is encoded as the king capturing its own rook.
+pub fn encode_move(mv: &Move) -> u16 {
+    let from = mv.from().map(|sq| sq.to_u32()).unwrap_or(0) as u16;
+    let to = mv.to().to_u32() as u16;
+    let promotion = match mv.promotion() {
+        Some(Role::Knight) => 1,
+        Some(Role::Bishop) => 2,
+        Some(Role::Rook) => 3,
+        Some(Role::Queen) => 4,
+        _ => 0,
+    };
+    to | (from << 6) | (promotion << 12)
+}
+
+/// Find the legal move in `position` that matches a packed Polyglot move.
+pub fn decode_move(position: &Chess, mv: u16) -> Option<Move> {
+    position
+        .legal_moves()
+        .into_iter()
+        .find(|legal| encode_move(legal) == mv)
+}
+
+/// Which games and plies contribute to an opening book.
+#[derive(Debug, Clone, Builder, PartialEq)]
+pub struct BookConfig {
+    /// Only the first `max_ply` half-moves of each game are added to the book.
+    #[builder(default = "20")]
+    pub max_ply: usize,
+    /// Moves played fewer times than this in a position are left out.
+    #[builder(default = "1")]
+    pub min_occurrences: u32,
+    /// If set, both players must be rated at least this much.
+    #[builder(default)]
+    pub min_elo: Option<u32>,
+    /// If non-empty, only games of these types are used.
+    #[builder(default)]
+    pub game_types: Vec<GameType>,
+}
+
+impl BookConfig {
+    pub fn builder() -> BookConfigBuilder {
+        BookConfigBuilder::default()
+    }
+
+    /// Whether a game passes the rating and game type filters.
+    pub fn accepts(&self, game: &ChessGame) -> bool {
+        let rated_enough = self
+            .min_elo
+            .is_none_or(|min| game.white_player_elo >= min && game.black_player_elo >= min);
+        let right_type = self.game_types.is_empty() || self.game_types.contains(&game.game_type);
+        rated_enough && right_type
+    }
+}
+
+impl Default for BookConfig {
+    fn default() -> Self {
+        Self::builder().build().expect("all fields have defaults")
+    }
+}
+
+/// Win/draw/loss counts for one move in one position, from the mover's point of view.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MoveStats {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl MoveStats {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// Polyglot-style score: two points per win and one per draw, so that the
+    /// weight grows both with how often a move is played and how well it scores.
+    pub fn points(&self) -> u64 {
+        2 * self.wins as u64 + self.draws as u64
+    }
+}
+
+/// Collects move statistics from games and turns them into a Polyglot book.
+#[derive(Debug, Default)]
+pub struct BookBuilder {
+    config: BookConfig,
+    stats: HashMap<(u64, u16), MoveStats>,
+}
+
+/// Collects the positions and moves of a single game, with the side that moved.
+#[derive(Default)]
+struct BookVisitor {
+    plies: Vec<(u64, u16, Color)>,
+}
+
+impl PlyVisitor for BookVisitor {
+    fn visit_ply(&mut self, ply: &Ply<'_>) {
+        let key = polyglot_key(ply.position);
+        self.plies
+            .push((key, encode_move(ply.mv), ply.position.turn()));
+    }
+}
+
+impl BookBuilder {
+    pub fn new(config: BookConfig) -> Self {
+        Self {
+            config,
+            stats: HashMap::new(),
+        }
+    }
+
+    /// Add the opening moves of a game, if it passes the configured filters. A game
+    /// without a winner counts as a draw, unless it is unfinished, in which case it is
+    /// skipped.
+    ///
+    /// The game is only added once all of its opening moves replay, so a game with an
+    /// illegal move leaves the statistics untouched.
+    ///
+    /// # Returns
+    ///
+    /// * `true` if the game was used, `false` if it was filtered out.
+    pub fn add_game(&mut self, game: &ChessGame) -> Result<bool> {
+        if game.termination_type == TerminationType::Unterminated || !self.config.accepts(game) {
+            return Ok(false);
+        }
+        let moves = &game.moves[..game.moves.len().min(self.config.max_ply)];
+        let mut visitor = BookVisitor::default();
+        replay(moves, &mut [&mut visitor])?;
+        let winner = game.winner.as_ref().map(|w| match w {
+            Winner::White => Color::White,
+            Winner::Black => Color::Black,
+        });
+        for (key, mv, mover) in visitor.plies {
+            let stats = self.stats.entry((key, mv)).or_default();
+            match winner {
+                Some(winner) if winner == mover => stats.wins += 1,
+                Some(_) => stats.losses += 1,
+                None => stats.draws += 1,
+            }
+        }
+        Ok(true)
+    }
+
+    /// Build the book entries, sorted by key and then by descending weight as Polyglot requires.
+    ///
+    /// Weights are the [`MoveStats::points`] of each move, scaled down per position
+    /// if necessary so the largest fits in a `u16`. Moves with no points are dropped.
+    pub fn build(&self) -> Vec<PolyglotEntry> {
+        let mut by_key: HashMap<u64, Vec<(u16, u64)>> = HashMap::new();
+        for (&(key, mv), stats) in &self.stats {
+            if stats.games() >= self.config.min_occurrences && stats.points() > 0 {
+                by_key.entry(key).or_default().push((mv, stats.points()));
+            }
+        }
+
+        let mut entries = Vec::new();
+        for (key, moves) in by_key {
+            let max = moves.iter().map(|&(_, points)| points).max().unwrap_or(0);
+            for (mv, points) in moves {
+                let weight = if max > u16::MAX as u64 {
+                    (points * u16::MAX as u64 / max).max(1)
+                } else {
+                    points
+                };
+                entries.push(PolyglotEntry {
+                    key,
+                    mv,
+                    weight: weight as u16,
+                    learn: 0,
+                });
+            }
+        }
+        entries.sort_by(|a, b| {
+            a.key
+                .cmp(&b.key)
+                .then(b.weight.cmp(&a.weight))
+                .then(a.mv.cmp(&b.mv))
+        });
+        entries
+    }
+}
+
+/// Write book entries to a Polyglot `.bin` file.
+///
+/// # Arguments
+///
+/// * `entries` - Entries sorted by key, as returned by [`BookBuilder::build`].
+/// * `output_path` - The path for the output book.
+pub fn write_polyglot_book(entries: &[PolyglotEntry], output_path: &str) -> Result<()> {
+    write_atomically(output_path, |file| {
+        let mut writer = BufWriter::new(file);
+        for entry in entries {
+            writer.write_all(&entry.to_bytes())?;
+        }
+        writer.flush()?;
+        Ok(())
+    })
+}
+
+/// Read all entries of a Polyglot `.bin` file.
+pub fn read_polyglot_book(path: &str) -> Result<Vec<PolyglotEntry>> {
+    let bytes = fs::read(path)?;
+    if bytes.len() % ENTRY_SIZE != 0 {
+        return Err(anyhow!(
+            "Polyglot book {} has a size of {} bytes, which is not a multiple of {}",
+            path,
+            bytes.len(),
+            ENTRY_SIZE
+        ));
+    }
+    Ok(bytes
+        .chunks_exact(ENTRY_SIZE)
+        .map(|chunk| PolyglotEntry::from_bytes(chunk.try_into().unwrap()))
+        .collect())
+}
+
+/// Look up the book moves for a position, with their weights, in book order.
+///
+/// # Arguments
+///
+/// * `entries` - Entries sorted by key.
+/// * `position` - The position to look up.
+pub fn book_moves(entries: &[PolyglotEntry], position: &Chess) -> Vec<(Move, u16)> {
+    let key = polyglot_key(position);
+    let start = entries.partition_point(|e| e.key < key);
+    entries[start..]
+        .iter()
+        .take_while(|e| e.key == key)
+        .filter_map(|e| decode_move(position, e.mv).map(|mv| (mv, e.weight)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movetext::parse_movetext;
+    use crate::test_game;
+    use shakmaty::{CastlingMode, Square};
+
+    fn game(movetext: &str, winner: Option<Winner>, elo: u32, game_type: GameType) -> ChessGame {
+        let mut game = test_game("g");
+        game.game_type = game_type;
+        game.white_player_elo = elo;
+        game.black_player_elo = elo;
+        game.winner = winner;
+        game.moves = parse_movetext(movetext);
+        game
+    }
+
+    #[test]
+    fn test_encode_move() {
+        let e2e4 = Move::Normal {
+            role: Role::Pawn,
+            from: Square::E2,
+            capture: None,
+            to: Square::E4,
+            promotion: None,
+        };
+        assert_eq!(encode_move(&e2e4), 0x031c);
+        let castle = Move::Castle {
+            king: Square::E1,
+            rook: Square::H1,
+        };
+        assert_eq!(encode_move(&castle), 0x0107);
+        assert_eq!(decode_move(&Chess::default(), 0x031c), Some(e2e4));
+    }
+
+    #[test]
+    fn test_entry_round_trip() {
+        let entry = PolyglotEntry {
+            key: 0x463b_9618_1691_fc9c,
+            mv: 0x031c,
+            weight: 42,
+            learn: 7,
+        };
+        assert_eq!(PolyglotEntry::from_bytes(&entry.to_bytes()), entry);
+        assert_eq!(polyglot_key(&Chess::default()), 0x463b_9618_1691_fc9c);
+    }
+
+    #[test]
+    fn test_build_book() {
+        let config = BookConfig::builder()
+            .max_ply(2)
+            .min_occurrences(2)
+            .min_elo(Some(2200))
+            .game_types(vec![GameType::Rapid])
+            .build()
+            .unwrap();
+        let mut builder = BookBuilder::new(config);
+        let games = [
+            game(
+                "1. e4 e5 2. Nf3",
+                Some(Winner::White),
+                2300,
+                GameType::Rapid,
+            ),
+            game("1. e4 c5 2. Nf3", None, 2300, GameType::Rapid),
+            game(
+                "1. e4 e5 2. Nf3",
+                Some(Winner::Black),
+                2300,
+                GameType::Rapid,
+            ),
+            game("1. d4 d5", Some(Winner::White), 2300, GameType::Rapid),
+            game("1. d4 d5", Some(Winner::White), 2000, GameType::Rapid),
+            game("1. d4 d5", Some(Winner::White), 2300, GameType::Blitz),
+        ];
+        let used: Vec<bool> = games.iter().map(|g| builder.add_game(g).unwrap()).collect();
+        assert_eq!(used, vec![true, true, true, true, false, false]);
+
+        let entries = builder.build();
+        let start = book_moves(&entries, &Chess::default());
+        // 1. e4 scored 2 + 1 + 0 points over 3 games; 1. d4 only occurred once.
+        assert_eq!(start.len(), 1);
+        assert_eq!(
+            start[0].0.to_uci(CastlingMode::Standard).to_string(),
+            "e2e4"
+        );
+        assert_eq!(start[0].1, 3);
+        // 2. Nf3 is beyond max_ply, so no White moves are stored after 1... e5.
+        assert_eq!(entries.len(), 2);
+    }
+
+    #[test]
+    fn test_write_and_read_book() {
+        let mut builder = BookBuilder::new(BookConfig::default());
+        builder
+            .add_game(&game(
+                "1. e4 e5 2. Nf3 Nc6 3. Bc4 Nf6 4. O-O",
+                Some(Winner::White),
+                1500,
+                GameType::Blitz,
+            ))
+            .unwrap();
+        let entries = builder.build();
+        // Black's moves lost, so only White's four moves carry any weight.
+        assert_eq!(entries.len(), 4);
+
+        let path = std::env::temp_dir().join(format!("chess_rs_book_{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+        write_polyglot_book(&entries, path).unwrap();
+        let read_back = read_polyglot_book(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(read_back, entries);
+
+        let castle = read_back
+            .iter()
+            .find(|e| e.mv == 0x0107)
+            .expect("castling move in book");
+        assert_eq!(castle.weight, 2);
+    }
+
+    #[test]
+    fn test_skip_unfinished_game() {
+        let mut builder = BookBuilder::new(BookConfig::default());
+        let mut unfinished = game("1. e4 e5", None, 1500, GameType::Blitz);
+        unfinished.termination_type = TerminationType::Unterminated;
+        assert!(!builder.add_game(&unfinished).unwrap());
+        assert!(builder.build().is_empty());
+    }
+
+    #[test]
+    fn test_illegal_game_leaves_book_untouched() {
+        let mut builder = BookBuilder::new(BookConfig::default());
+        let illegal = game(
+            "1. e4 e5 2. Ke3",
+            Some(Winner::White),
+            1500,
+            GameType::Blitz,
+        );
+        assert!(builder.add_game(&illegal).is_err());
+        assert!(builder.build().is_empty());
+    }
+}
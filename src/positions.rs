@@ -0,0 +1,198 @@
+use std::time::Duration;
+
+use polars::prelude::*;
+use shakmaty::fen::Fen;
+use shakmaty::{EnPassantMode, Position};
+
+use crate::movetext::Eval;
+use crate::output::{TableRow, TableWriter};
+use crate::replay::{Ply, PlyVisitor};
+use crate::GameType;
+
+/// One row of the positions table: a single ply of a game, with the position it was
+/// played from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionRow {
+    pub game_id: String,
+    /// Only used to partition the table; not a column.
+    pub game_type: GameType,
+    /// 1-based half-move number.
+    pub ply: u32,
+    /// FEN of the position before the move.
+    pub fen: String,
+    pub san: String,
+    pub uci: Option<String>,
+    /// Clock time remaining after the move.
+    pub clock: Option<Duration>,
+    /// Engine evaluation after the move, from White's point of view.
+    pub eval: Option<Eval>,
+    pub white_to_move: bool,
+}
+
+/// A [`PlyVisitor`] that collects a [`PositionRow`] for every ply of a game.
+pub struct PositionRecorder {
+    game_id: String,
+    game_type: GameType,
+    pub rows: Vec<PositionRow>,
+}
+
+impl PositionRecorder {
+    pub fn new(game_id: &str, game_type: &GameType) -> Self {
+        Self {
+            game_id: game_id.to_string(),
+            game_type: game_type.clone(),
+            rows: Vec::new(),
+        }
+    }
+}
+
+impl PlyVisitor for PositionRecorder {
+    fn visit_ply(&mut self, ply: &Ply<'_>) {
+        self.rows.push(PositionRow {
+            game_id: self.game_id.clone(),
+            game_type: self.game_type.clone(),
+            ply: ply.number,
+            fen: Fen::from_position(ply.position, EnPassantMode::Legal).to_string(),
+            san: ply.record.san.clone(),
+            uci: Some(ply.uci()),
+            clock: ply.record.clock,
+            eval: ply.record.eval,
+            white_to_move: ply.position.turn().is_white(),
+        });
+    }
+}
+
+/// The schema of the positions table. Evals are encoded as in [`Eval::to_encoded`].
+pub fn position_schema() -> Schema {
+    Schema::from_iter([
+        Field::new("game_id", DataType::Utf8),
+        Field::new("ply", DataType::UInt32),
+        Field::new("fen", DataType::Utf8),
+        Field::new("san", DataType::Utf8),
+        Field::new("uci", DataType::Utf8),
+        Field::new("clock", DataType::Duration(TimeUnit::Milliseconds)),
+        Field::new("eval", DataType::Int32),
+        Field::new("side_to_move", DataType::Categorical(None)),
+    ])
+}
+
+/// Convert position rows into a DataFrame with the [`position_schema`].
+pub fn positions_to_dataframe(rows: &[PositionRow]) -> PolarsResult<DataFrame> {
+    let utf8 = |name: &str, f: fn(&PositionRow) -> &str| {
+        Series::new(name, rows.iter().map(f).collect::<Vec<&str>>())
+    };
+    let clocks: Vec<Option<i64>> = rows
+        .iter()
+        .map(|r| r.clock.map(|c| c.as_millis() as i64))
+        .collect();
+    let side_to_move: Vec<&str> = rows
+        .iter()
+        .map(|r| if r.white_to_move { "White" } else { "Black" })
+        .collect();
+
+    DataFrame::new(vec![
+        utf8("game_id", |r| &r.game_id),
+        Series::new("ply", rows.iter().map(|r| r.ply).collect::<Vec<u32>>()),
+        utf8("fen", |r| &r.fen),
+        utf8("san", |r| &r.san),
+        Series::new(
+            "uci",
+            rows.iter()
+                .map(|r| r.uci.as_deref())
+                .collect::<Vec<Option<&str>>>(),
+        ),
+        Series::new("clock", clocks).cast(&DataType::Duration(TimeUnit::Milliseconds))?,
+        Series::new(
+            "eval",
+            rows.iter()
+                .map(|r| r.eval.map(|e| e.to_encoded()))
+                .collect::<Vec<Option<i32>>>(),
+        ),
+        Series::new("side_to_move", side_to_move).cast(&DataType::Categorical(None))?,
+    ])
+}
+
+impl TableRow for PositionRow {
+    const TABLE: &'static str = "positions";
+
+    fn game_type(&self) -> &GameType {
+        &self.game_type
+    }
+
+    fn to_dataframe(rows: &[Self]) -> PolarsResult<DataFrame> {
+        positions_to_dataframe(rows)
+    }
+}
+
+/// Writes the positions table.
+pub type PositionWriter = TableWriter<PositionRow>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movetext::parse_movetext;
+    use crate::output::{OutputFormat, OutputLayout};
+    use crate::parquet::read_games_parquet;
+    use crate::replay::replay;
+
+    /// The positions of a rapid game, recorded while replaying its moves.
+    fn positions(game_id: &str, movetext: &str) -> Vec<PositionRow> {
+        let mut recorder = PositionRecorder::new(game_id, &GameType::Rapid);
+        replay(&parse_movetext(movetext), &mut [&mut recorder]).unwrap();
+        recorder.rows
+    }
+
+    #[test]
+    fn test_record_positions() {
+        let positions = positions(
+            "a",
+            "1. e4 { [%eval 0.2] [%clk 0:10:00] } 1... e5 { [%clk 0:09:58] }",
+        );
+        assert_eq!(positions.len(), 2);
+        assert_eq!(
+            positions[0].fen,
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+        );
+        assert_eq!(positions[1].ply, 2);
+        assert_eq!(positions[1].uci.as_deref(), Some("e7e5"));
+        assert!(!positions[1].white_to_move);
+    }
+
+    #[test]
+    fn test_write_positions_table() {
+        let root = std::env::temp_dir().join(format!("chess_rs_positions_{}", std::process::id()));
+        let root = root.to_str().unwrap();
+        let mut writer = PositionWriter::new(
+            root,
+            2016,
+            3,
+            OutputLayout::Hive,
+            OutputFormat::default(),
+            3,
+        );
+        let mut written = Vec::new();
+        for row in positions("a", "1. e4 { [%clk 0:10:00] } e5 2. Nf3")
+            .into_iter()
+            .chain(positions("b", "1. d4 d5"))
+        {
+            written.extend(writer.push(row).unwrap());
+        }
+        written.extend(writer.finish().unwrap());
+
+        assert_eq!(written.len(), 2);
+        assert!(written[1]
+            .path
+            .ends_with("/positions/year=2016/month=03/game_type=Rapid/part-002.parquet"));
+        let df = read_games_parquet(&written[0].path).unwrap();
+        assert_eq!(df.schema(), position_schema());
+        let clock = df.column("clock").unwrap().cast(&DataType::Int64).unwrap();
+        assert_eq!(clock.i64().unwrap().get(0), Some(600_000));
+        let side = df
+            .column("side_to_move")
+            .unwrap()
+            .cast(&DataType::Utf8)
+            .unwrap();
+        assert_eq!(side.utf8().unwrap().get(1), Some("Black"));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
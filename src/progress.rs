@@ -0,0 +1,446 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Serializer};
+
+/// How often a [`StageProgress`] reports while its stage is running.
+pub const REPORT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A long-running stage of processing a month.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Download,
+    Decompress,
+    Parse,
+    /// The month is complete; the event carries its totals.
+    Done,
+}
+
+impl FromStr for Stage {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "download" => Ok(Self::Download),
+            "decompress" => Ok(Self::Decompress),
+            "parse" => Ok(Self::Parse),
+            "done" => Ok(Self::Done),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Download => write!(f, "download"),
+            Self::Decompress => write!(f, "decompress"),
+            Self::Parse => write!(f, "parse"),
+            Self::Done => write!(f, "done"),
+        }
+    }
+}
+
+fn as_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// How far a stage of a month has got.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProgressEvent {
+    pub stage: Stage,
+    pub year: i32,
+    pub month: i32,
+    /// Bytes handled so far: downloaded, decompressed from the archive, or read from
+    /// the PGN.
+    pub bytes: u64,
+    /// Bytes the stage will handle in total, if known.
+    pub total_bytes: Option<u64>,
+    /// Bytes handled before the stage started, e.g. by an interrupted download that it
+    /// resumed. They count towards `bytes` but not towards the rate.
+    pub resumed_bytes: u64,
+    /// Failed attempts so far.
+    pub retries: u32,
+    /// Why the last attempt failed, while the stage waits to retry.
+    pub error: Option<String>,
+    /// Games parsed so far.
+    pub games: usize,
+    /// Time since the stage started, in seconds in JSON.
+    #[serde(rename = "elapsed_secs", serialize_with = "as_secs")]
+    pub elapsed: Duration,
+    /// Whether this is the stage's last event.
+    pub finished: bool,
+}
+
+/// Receives the progress events of every month being processed.
+pub trait ProgressSink: Send + Sync {
+    fn report(&self, event: &ProgressEvent);
+
+    /// Show a message about the run, e.g. a warning, without breaking up the progress
+    /// output. By default it is written to standard error.
+    fn message(&self, text: &str) {
+        eprintln!("{}", text);
+    }
+}
+
+/// Ignores progress, but still prints messages.
+impl ProgressSink for () {
+    fn report(&self, _event: &ProgressEvent) {}
+}
+
+/// Reports the progress of one stage of a month to a sink, at most every
+/// [`REPORT_INTERVAL`] until it finishes.
+pub struct StageProgress {
+    sink: Arc<dyn ProgressSink>,
+    event: ProgressEvent,
+    started: Instant,
+    last_report: Option<Instant>,
+}
+
+impl StageProgress {
+    pub fn new(sink: Arc<dyn ProgressSink>, stage: Stage, year: i32, month: i32) -> Self {
+        Self {
+            sink,
+            event: ProgressEvent {
+                stage,
+                year,
+                month,
+                bytes: 0,
+                total_bytes: None,
+                resumed_bytes: 0,
+                retries: 0,
+                error: None,
+                games: 0,
+                elapsed: Duration::ZERO,
+                finished: false,
+            },
+            started: Instant::now(),
+            last_report: None,
+        }
+    }
+
+    /// Set the number of bytes the stage will handle, once it is known.
+    pub fn set_total_bytes(&mut self, total_bytes: Option<u64>) {
+        self.event.total_bytes = total_bytes;
+    }
+
+    /// Record that the stage picks up after `bytes` bytes handled earlier, and report it.
+    pub fn resume_from(&mut self, bytes: u64) {
+        self.event.resumed_bytes = bytes;
+        self.event.bytes = bytes;
+        self.report();
+    }
+
+    /// Record that an attempt failed and will be retried after `delay`, and report it.
+    pub fn retrying(&mut self, error: &str, delay: Duration) {
+        self.event.retries += 1;
+        self.event.error = Some(format!(
+            "{}; retrying in {:.1}s",
+            error,
+            delay.as_secs_f64()
+        ));
+        self.report();
+    }
+
+    /// Record the bytes and games handled so far, reporting them if the last report is
+    /// long enough ago.
+    pub fn update(&mut self, bytes: u64, games: usize) {
+        self.event.error = None;
+        self.event.bytes = bytes;
+        self.event.games = games;
+        let now = Instant::now();
+        if self
+            .last_report
+            .is_none_or(|last| now - last >= REPORT_INTERVAL)
+        {
+            self.last_report = Some(now);
+            self.report();
+        }
+    }
+
+    /// Record that the bytes handled grew by `bytes`.
+    pub fn add_bytes(&mut self, bytes: u64) {
+        self.update(self.event.bytes + bytes, self.event.games);
+    }
+
+    /// Report the final counts of the stage.
+    pub fn finish(mut self, bytes: u64, games: usize) {
+        self.event.bytes = bytes;
+        self.event.games = games;
+        self.event.error = None;
+        self.event.finished = true;
+        self.report();
+    }
+
+    fn report(&mut self) {
+        self.event.elapsed = self.started.elapsed();
+        self.sink.report(&self.event);
+    }
+}
+
+/// A reader that reports the bytes read through it to a [`StageProgress`].
+pub struct ProgressReader<'a, R> {
+    inner: R,
+    progress: &'a mut StageProgress,
+}
+
+impl<'a, R: Read> ProgressReader<'a, R> {
+    pub fn new(inner: R, progress: &'a mut StageProgress) -> Self {
+        Self { inner, progress }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress.add_bytes(n as u64);
+        Ok(n)
+    }
+}
+
+/// Writes each event as a line of JSON, e.g. for job logs.
+pub struct JsonLinesProgress<W> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonLinesProgress<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<W: Write + Send> ProgressSink for JsonLinesProgress<W> {
+    fn report(&self, event: &ProgressEvent) {
+        let Ok(json) = serde_json::to_string(event) else {
+            return;
+        };
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        // Progress is best effort; a closed log must not stop processing.
+        let _ = writeln!(writer, "{}", json).and_then(|_| writer.flush());
+    }
+
+    /// Writes the message as a `{"message": ...}` line, so the output stays JSON lines.
+    fn message(&self, text: &str) {
+        let json = serde_json::json!({ "message": text });
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = writeln!(writer, "{}", json).and_then(|_| writer.flush());
+    }
+}
+
+/// Draws a progress bar per month on standard error, redrawn in place as events arrive.
+#[derive(Default)]
+pub struct TerminalProgress {
+    state: Mutex<TerminalState>,
+}
+
+#[derive(Default)]
+struct TerminalState {
+    /// The latest event per month.
+    months: BTreeMap<(i32, i32), ProgressEvent>,
+    /// Lines drawn last time.
+    drawn: usize,
+}
+
+impl TerminalProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TerminalState {
+    /// Redraw the bars in place, first printing `message` where they began so that it
+    /// scrolls up with the terminal instead of being drawn over.
+    fn draw(&mut self, message: Option<&str>) {
+        let mut out = String::new();
+        if self.drawn > 0 {
+            // Move back up over the previous drawing.
+            out.push_str(&format!("\x1b[{}A", self.drawn));
+        }
+        let message = message.into_iter().flat_map(str::lines).map(str::to_string);
+        let bars = self.months.values().map(render_line);
+        for line in message.chain(bars) {
+            out.push_str("\x1b[2K");
+            out.push_str(&line);
+            out.push('\n');
+        }
+        self.drawn = self.months.len();
+        let _ = io::stderr().write_all(out.as_bytes());
+    }
+}
+
+impl ProgressSink for TerminalProgress {
+    fn report(&self, event: &ProgressEvent) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state
+            .months
+            .insert((event.year, event.month), event.clone());
+        state.draw(None);
+    }
+
+    fn message(&self, text: &str) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.draw(Some(text));
+    }
+}
+
+/// One line of the terminal display, e.g.
+/// `2016-03 download   [#######.............]  35%  912.0 MB / 2.6 GB  31.5 MB/s  0:29`.
+pub fn render_line(event: &ProgressEvent) -> String {
+    let secs = event.elapsed.as_secs_f64();
+    let stage = event.stage.to_string();
+    let mut line = format!("{}-{:02} {:<10}", event.year, event.month, stage);
+    if event.stage == Stage::Done {
+        line.push_str(&format!(
+            " {} games in {}",
+            event.games,
+            format_elapsed(event.elapsed)
+        ));
+        return line;
+    }
+    match event.total_bytes.filter(|&total| total > 0) {
+        Some(total) => {
+            let fraction = (event.bytes as f64 / total as f64).min(1.0);
+            let filled = (fraction * 20.0).round() as usize;
+            line.push_str(&format!(
+                " [{}{}] {:>3}%  {} / {}",
+                "#".repeat(filled),
+                ".".repeat(20 - filled),
+                (fraction * 100.0).round(),
+                format_bytes(event.bytes),
+                format_bytes(total)
+            ));
+        }
+        None => line.push_str(&format!(" {}", format_bytes(event.bytes))),
+    }
+    if secs > 0.0 {
+        let new_bytes = event.bytes.saturating_sub(event.resumed_bytes);
+        line.push_str(&format!(
+            "  {}/s",
+            format_bytes((new_bytes as f64 / secs) as u64)
+        ));
+    }
+    if event.stage == Stage::Parse {
+        line.push_str(&format!("  {} games", event.games));
+        if secs > 0.0 {
+            line.push_str(&format!("  {:.0} games/s", event.games as f64 / secs));
+        }
+    }
+    line.push_str(&format!("  {}", format_elapsed(event.elapsed)));
+    if let Some(error) = &event.error {
+        line.push_str(&format!("  (attempt {} failed: {})", event.retries, error));
+    }
+    line
+}
+
+/// A byte count with a decimal unit, e.g. `2.6 GB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "kB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// A duration as `m:ss`, or `h:mm:ss` from an hour.
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(stage: Stage, bytes: u64, total_bytes: Option<u64>, games: usize) -> ProgressEvent {
+        ProgressEvent {
+            stage,
+            year: 2016,
+            month: 3,
+            bytes,
+            total_bytes,
+            resumed_bytes: 0,
+            retries: 0,
+            error: None,
+            games,
+            elapsed: Duration::from_secs(10),
+            finished: false,
+        }
+    }
+
+    #[test]
+    fn test_render_line() {
+        assert_eq!(
+            render_line(&event(Stage::Download, 500_000_000, Some(2_000_000_000), 0)),
+            "2016-03 download   [#####...............]  25%  500.0 MB / 2.0 GB  50.0 MB/s  0:10"
+        );
+        assert_eq!(
+            render_line(&event(Stage::Parse, 1_000, None, 25_000)),
+            "2016-03 parse      1.0 kB  100 B/s  25000 games  2500 games/s  0:10"
+        );
+        let mut retrying = event(Stage::Download, 1_000_000, Some(2_000_000), 0);
+        retrying.resumed_bytes = 500_000;
+        retrying.retries = 1;
+        retrying.error = Some("503 Service Unavailable; retrying in 2.0s".to_string());
+        assert_eq!(
+            render_line(&retrying),
+            "2016-03 download   [##########..........]  50%  1.0 MB / 2.0 MB  50.0 kB/s  0:10  \
+             (attempt 1 failed: 503 Service Unavailable; retrying in 2.0s)"
+        );
+        assert_eq!(
+            render_line(&event(Stage::Done, 0, None, 25_000)),
+            "2016-03 done       25000 games in 0:10"
+        );
+        assert_eq!(format_elapsed(Duration::from_secs(3723)), "1:02:03");
+        assert_eq!(Stage::from_str("Parse"), Ok(Stage::Parse));
+    }
+
+    #[test]
+    fn test_json_lines_progress() {
+        let sink = Arc::new(JsonLinesProgress::new(Vec::new()));
+        let mut progress = StageProgress::new(sink.clone(), Stage::Decompress, 2016, 3);
+        progress.set_total_bytes(Some(100));
+        let mut reader = ProgressReader::new(&b"0123456789"[..], &mut progress);
+        io::copy(&mut reader, &mut io::sink()).unwrap();
+        // Only the first update is reported within the interval.
+        progress.update(50, 0);
+        progress.finish(100, 0);
+        sink.message("No published checksum");
+
+        let sink = Arc::try_unwrap(sink).ok().unwrap();
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["stage"], "decompress");
+        assert_eq!(lines[0]["month"], 3);
+        assert_eq!(lines[0]["bytes"], 10);
+        assert_eq!(lines[0]["total_bytes"], 100);
+        assert_eq!(lines[1]["bytes"], 100);
+        assert_eq!(lines[1]["finished"], true);
+        assert!(lines[1]["elapsed_secs"].is_f64());
+        assert_eq!(lines[2]["message"], "No published checksum");
+    }
+}
@@ -0,0 +1,325 @@
+use std::fs;
+use std::io::{BufRead, BufReader};
+
+use anyhow::{anyhow, Result};
+use polars::prelude::*;
+
+use crate::lichess_game_id;
+use crate::output::{OutputFormat, WrittenPart};
+
+/// A puzzle from the Lichess puzzle database, `lichess_db_puzzle.csv.zst`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Puzzle {
+    pub puzzle_id: String,
+    /// FEN of the position before the opponent's move that sets up the puzzle.
+    pub fen: String,
+    /// The solution in UCI, starting with the opponent's move.
+    pub moves: Vec<String>,
+    pub rating: u32,
+    pub rating_deviation: u32,
+    /// From -100 to 100, from the votes of the players who solved it.
+    pub popularity: i32,
+    pub nb_plays: u32,
+    pub themes: Vec<String>,
+    /// The game the puzzle was taken from, e.g. `https://lichess.org/787zsVup/black#48`.
+    pub game_url: String,
+    pub opening_tags: Vec<String>,
+}
+
+impl Puzzle {
+    /// The id of the game the puzzle was taken from, to join it to
+    /// [`ChessGame::game_id`](crate::ChessGame::game_id).
+    pub fn game_id(&self) -> Option<&str> {
+        lichess_game_id(&self.game_url)
+    }
+}
+
+/// Parse a row of the puzzle CSV. None of its fields are quoted: lists are separated by
+/// spaces. Rows from before opening tags were published have only nine fields.
+pub fn parse_puzzle_line(line: &str) -> Result<Puzzle> {
+    let fields: Vec<&str> = line.split(',').collect();
+    if fields.len() != 9 && fields.len() != 10 {
+        return Err(anyhow!("expected 9 or 10 fields, found {}", fields.len()));
+    }
+    let list = |field: &str| field.split_whitespace().map(str::to_string).collect();
+    let number = |i: usize, name: &str| {
+        fields[i]
+            .parse::<i64>()
+            .map_err(|_| anyhow!("invalid {} '{}'", name, fields[i]))
+    };
+    Ok(Puzzle {
+        puzzle_id: fields[0].to_string(),
+        fen: fields[1].to_string(),
+        moves: list(fields[2]),
+        rating: number(3, "rating")? as u32,
+        rating_deviation: number(4, "rating deviation")? as u32,
+        popularity: number(5, "popularity")? as i32,
+        nb_plays: number(6, "number of plays")? as u32,
+        themes: list(fields[7]),
+        game_url: fields[8].to_string(),
+        opening_tags: fields.get(9).copied().map(list).unwrap_or_default(),
+    })
+}
+
+/// An iterator over the puzzles of a CSV reader, one row at a time, skipping the header.
+pub struct PuzzleReader<R> {
+    reader: R,
+    line: String,
+    /// 1-based number of the last line read.
+    line_number: usize,
+}
+
+impl<R: BufRead> PuzzleReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+            line_number: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for PuzzleReader<R> {
+    type Item = Result<Puzzle>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e.into())),
+            }
+            self.line_number += 1;
+            let line = self.line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() || (self.line_number == 1 && line.starts_with("PuzzleId,")) {
+                continue;
+            }
+            return Some(
+                parse_puzzle_line(line).map_err(|e| anyhow!("line {}: {}", self.line_number, e)),
+            );
+        }
+    }
+}
+
+/// Read the puzzles of a CSV file, decompressing it on the fly if it ends in `.zst`.
+pub fn open_puzzles(path: &str) -> Result<PuzzleReader<Box<dyn BufRead + Send>>> {
+    let file = fs::File::open(path)?;
+    let reader: Box<dyn BufRead + Send> = if path.ends_with(".zst") {
+        Box::new(BufReader::new(zstd::stream::read::Decoder::new(file)?))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    Ok(PuzzleReader::new(reader))
+}
+
+/// The schema of the puzzles table. `game_id` joins puzzles to the games table, and is
+/// null for the few puzzles whose game URL is not a Lichess game.
+pub fn puzzle_schema() -> Schema {
+    let list = || DataType::List(Box::new(DataType::Utf8));
+    Schema::from_iter([
+        Field::new("puzzle_id", DataType::Utf8),
+        Field::new("game_id", DataType::Utf8),
+        Field::new("fen", DataType::Utf8),
+        Field::new("moves", list()),
+        Field::new("rating", DataType::UInt32),
+        Field::new("rating_deviation", DataType::UInt32),
+        Field::new("popularity", DataType::Int32),
+        Field::new("nb_plays", DataType::UInt32),
+        Field::new("themes", list()),
+        Field::new("game_url", DataType::Utf8),
+        Field::new("opening_tags", list()),
+    ])
+}
+
+/// A list of strings per puzzle.
+fn list_column(
+    name: &str,
+    puzzles: &[Puzzle],
+    f: fn(&Puzzle) -> &[String],
+) -> PolarsResult<Series> {
+    let dtype = DataType::List(Box::new(DataType::Utf8));
+    if puzzles.is_empty() {
+        // Polars cannot infer the list type from an empty `Vec<Series>`.
+        return Ok(Series::new_empty(name, &dtype));
+    }
+    let lists: Vec<Series> = puzzles
+        .iter()
+        .map(|p| Series::new("", f(p).iter().map(String::as_str).collect::<Vec<&str>>()))
+        .collect();
+    Series::new(name, lists).cast(&dtype)
+}
+
+/// Convert puzzles into a DataFrame with the [`puzzle_schema`].
+pub fn puzzles_to_dataframe(puzzles: &[Puzzle]) -> PolarsResult<DataFrame> {
+    let utf8 = |name: &str, f: fn(&Puzzle) -> &str| {
+        Series::new(name, puzzles.iter().map(f).collect::<Vec<&str>>())
+    };
+    let u32s = |name: &str, f: fn(&Puzzle) -> u32| {
+        Series::new(name, puzzles.iter().map(f).collect::<Vec<u32>>())
+    };
+    DataFrame::new(vec![
+        utf8("puzzle_id", |p| &p.puzzle_id),
+        Series::new(
+            "game_id",
+            puzzles
+                .iter()
+                .map(Puzzle::game_id)
+                .collect::<Vec<Option<&str>>>(),
+        ),
+        utf8("fen", |p| &p.fen),
+        list_column("moves", puzzles, |p| &p.moves)?,
+        u32s("rating", |p| p.rating),
+        u32s("rating_deviation", |p| p.rating_deviation),
+        Series::new(
+            "popularity",
+            puzzles.iter().map(|p| p.popularity).collect::<Vec<i32>>(),
+        ),
+        u32s("nb_plays", |p| p.nb_plays),
+        list_column("themes", puzzles, |p| &p.themes)?,
+        utf8("game_url", |p| &p.game_url),
+        list_column("opening_tags", puzzles, |p| &p.opening_tags)?,
+    ])
+}
+
+/// Buffers puzzles and writes them to `{root}/puzzles/part-NNN.{ext}`, in files of at
+/// most `chunk_size` puzzles each.
+pub struct PuzzleWriter {
+    root: String,
+    format: OutputFormat,
+    chunk_size: usize,
+    buffer: Vec<Puzzle>,
+    /// Number of files written so far.
+    parts: usize,
+}
+
+impl PuzzleWriter {
+    pub fn new(root: &str, format: OutputFormat, chunk_size: usize) -> Self {
+        Self {
+            root: root.to_string(),
+            format,
+            chunk_size,
+            buffer: Vec::new(),
+            parts: 0,
+        }
+    }
+
+    /// Add a puzzle, writing the buffer out if it is full.
+    ///
+    /// # Returns
+    ///
+    /// * The file that was written, if any.
+    pub fn push(&mut self, puzzle: Puzzle) -> Result<Option<WrittenPart>> {
+        self.buffer.push(puzzle);
+        if self.buffer.len() >= self.chunk_size {
+            return self.flush().map(Some);
+        }
+        Ok(None)
+    }
+
+    fn flush(&mut self) -> Result<WrittenPart> {
+        let puzzles = std::mem::take(&mut self.buffer);
+        self.parts += 1;
+        let dir = format!("{}/puzzles", self.root);
+        fs::create_dir_all(&dir)?;
+        let path = format!("{}/part-{:03}.{}", dir, self.parts, self.format.extension());
+        let mut df = puzzles_to_dataframe(&puzzles)?;
+        self.format.write_dataframe(&mut df, &path)?;
+        Ok(WrittenPart {
+            table: "puzzles".to_string(),
+            path,
+            rows: puzzles.len(),
+        })
+    }
+
+    /// Write out the remaining puzzles.
+    pub fn finish(mut self) -> Result<Vec<WrittenPart>> {
+        if self.buffer.is_empty() {
+            return Ok(Vec::new());
+        }
+        Ok(vec![self.flush()?])
+    }
+}
+
+/// Write every puzzle of a CSV file, compressed or not, to the puzzles table under
+/// `root`, e.g. as Parquet with [`OutputFormat::default`].
+///
+/// # Returns
+///
+/// * The files written.
+pub fn write_puzzle_table(
+    input_path: &str,
+    root: &str,
+    format: OutputFormat,
+    chunk_size: usize,
+) -> Result<Vec<WrittenPart>> {
+    let mut writer = PuzzleWriter::new(root, format, chunk_size);
+    let mut written = Vec::new();
+    for puzzle in open_puzzles(input_path)? {
+        written.extend(writer.push(puzzle?)?);
+    }
+    written.extend(writer.finish()?);
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parquet::read_games_parquet;
+    use std::io::Write;
+
+    const CSV: &str = "PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,GameUrl,OpeningTags
+00008,r6k/pp2r2p/4Rp1Q/3p4/8/1N1P2R1/PqP2bPP/7K b - - 0 24,f2g3 e6e7 b2b1 b3c1 b1c1 h6c1,1913,75,94,6230,crushing hangingPiece long middlegame,https://lichess.org/787zsVup/black#48,
+0000D,5rk1/1p3ppp/pq3b2/8/8/1P1Q1N2/P4PPP/3R2K1 w - - 2 27,d3d6 f8d8 d6d8 f6d8,1580,73,97,1341,advantage endgame short,https://lichess.org/F8M8OS71#53,
+0009B,r2qr1k1/b1p2ppp/pp4n1/P1P1p3/4P1n1/B2P2Pb/3NBP1P/RN1QR1K1 b - - 1 16,b6c5 e2g4 h3g4 d1g4,1102,75,85,516,advantage middlegame short,https://lichess.org/4MWQCxQ6/black#32,Kings_Pawn_Game Kings_Pawn_Game_Leonardis_Variation
+";
+
+    #[test]
+    fn test_read_puzzles() {
+        let puzzles: Vec<Puzzle> = PuzzleReader::new(CSV.as_bytes())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(puzzles.len(), 3);
+        assert_eq!(puzzles[0].moves[0], "f2g3");
+        assert_eq!(puzzles[0].themes.len(), 4);
+        assert_eq!(puzzles[0].game_id(), Some("787zsVup"));
+        assert_eq!(puzzles[1].game_id(), Some("F8M8OS71"));
+        assert_eq!(puzzles[2].popularity, 85);
+        assert_eq!(puzzles[2].opening_tags.len(), 2);
+
+        let error = PuzzleReader::new("00008,fen,e2e4,x,75,94,6230,,,\n".as_bytes())
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "line 1: invalid rating 'x'");
+    }
+
+    #[test]
+    fn test_write_puzzle_table() {
+        let root = std::env::temp_dir().join(format!("chess_rs_puzzles_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let root = root.to_str().unwrap();
+        let input = format!("{}/lichess_db_puzzle.csv.zst", root);
+        let mut encoder = zstd::stream::write::Encoder::new(fs::File::create(&input).unwrap(), 0)
+            .unwrap()
+            .auto_finish();
+        encoder.write_all(CSV.as_bytes()).unwrap();
+        drop(encoder);
+
+        let written = write_puzzle_table(&input, root, OutputFormat::default(), 2).unwrap();
+        assert_eq!(written.len(), 2);
+        assert_eq!(
+            written[1].path,
+            format!("{}/puzzles/part-002.parquet", root)
+        );
+        assert_eq!(written[1].rows, 1);
+
+        let df = read_games_parquet(&written[0].path).unwrap();
+        assert_eq!(df.schema(), puzzle_schema());
+        let game_ids = df.column("game_id").unwrap();
+        assert_eq!(game_ids.utf8().unwrap().get(0), Some("787zsVup"));
+        let moves = df.column("moves").unwrap().list().unwrap().get(1).unwrap();
+        assert_eq!(moves.len(), 4);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,422 @@
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Result;
+use chrono::{Datelike, NaiveDate};
+use polars::prelude::*;
+
+use crate::dataframe::{dataframe_to_games, game_schema};
+use crate::output::OutputLayout;
+use crate::{ChessGame, GameType};
+
+/// A filter over the games table written under an output root, e.g.
+///
+/// ```no_run
+/// use chess_rs::query::GameQuery;
+/// use chess_rs::GameType;
+/// use chrono::NaiveDate;
+///
+/// let start = NaiveDate::from_ymd_opt(2016, 1, 1).unwrap();
+/// let end = NaiveDate::from_ymd_opt(2016, 7, 1).unwrap();
+/// let games = GameQuery::new()
+///     .game_type(GameType::Blitz)
+///     .elo_between(1800, 2200)
+///     .dates(start..end)
+///     .eco_prefix("B")
+///     .lazy()?
+///     .collect()?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+/// Months and game types outside the filter are pruned by path before anything is read,
+/// and the remaining filters are pushed down into the Parquet scan. Only Parquet output
+/// can be queried.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameQuery {
+    root: String,
+    layout: OutputLayout,
+    game_types: Vec<GameType>,
+    elo: Option<(u32, u32)>,
+    dates: Option<Range<NaiveDate>>,
+    eco_prefix: Option<String>,
+}
+
+impl Default for GameQuery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GameQuery {
+    /// A query for every game under `lichess_data` in the flat layout.
+    pub fn new() -> Self {
+        Self {
+            root: "lichess_data".to_string(),
+            layout: OutputLayout::default(),
+            game_types: Vec::new(),
+            elo: None,
+            dates: None,
+            eco_prefix: None,
+        }
+    }
+
+    /// Read from another output root.
+    pub fn root(mut self, root: &str) -> Self {
+        self.root = root.to_string();
+        self
+    }
+
+    /// Read files written in another layout.
+    pub fn layout(mut self, layout: OutputLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Only games of this type. Call again to also include other types.
+    pub fn game_type(mut self, game_type: GameType) -> Self {
+        if !self.game_types.contains(&game_type) {
+            self.game_types.push(game_type);
+        }
+        self
+    }
+
+    /// Only games where both players' Elo is between `min` and `max`, inclusive.
+    pub fn elo_between(mut self, min: u32, max: u32) -> Self {
+        self.elo = Some((min, max));
+        self
+    }
+
+    /// Only games played on a date in the range. Games without a date are excluded.
+    pub fn dates(mut self, dates: Range<NaiveDate>) -> Self {
+        self.dates = Some(dates);
+        self
+    }
+
+    /// Only games whose ECO code starts with `prefix`, e.g. `B` or `C4`.
+    pub fn eco_prefix(mut self, prefix: &str) -> Self {
+        self.eco_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Whether a month may hold games in the date range.
+    fn includes_month(&self, year: i32, month: u32) -> bool {
+        let Some(dates) = &self.dates else {
+            return true;
+        };
+        let month = (year, month);
+        month >= (dates.start.year(), dates.start.month())
+            && dates
+                .end
+                .pred_opt()
+                .is_some_and(|last| month <= (last.year(), last.month()))
+    }
+
+    /// The Parquet files the query reads, with the game type of their partition for
+    /// the Hive layout, sorted by path.
+    fn partitions(&self) -> Result<Vec<(String, Option<GameType>)>> {
+        let (months_dir, year_key, month_key) = match self.layout {
+            OutputLayout::Flat => (self.root.clone(), "", ""),
+            OutputLayout::Hive => (format!("{}/games", self.root), "year=", "month="),
+        };
+        let mut files = Vec::new();
+        for (year_dir, year) in numbered_dirs(Path::new(&months_dir), year_key)? {
+            for (month_dir, month) in numbered_dirs(&year_dir, month_key)? {
+                if !self.includes_month(year as i32, month) {
+                    continue;
+                }
+                match self.layout {
+                    OutputLayout::Flat => {
+                        let prefix = format!("{}-{:02}__", year, month);
+                        for path in parquet_files(&month_dir)? {
+                            if file_name(&path).starts_with(&prefix) {
+                                files.push((path, None));
+                            }
+                        }
+                    }
+                    OutputLayout::Hive => {
+                        for entry in fs::read_dir(&month_dir)? {
+                            let dir = entry?.path();
+                            let Some(game_type) = file_name(&dir.to_string_lossy())
+                                .strip_prefix("game_type=")
+                                .and_then(|t| GameType::from_str(t).ok())
+                            else {
+                                continue;
+                            };
+                            if !self.game_types.is_empty() && !self.game_types.contains(&game_type)
+                            {
+                                continue;
+                            }
+                            for path in parquet_files(&dir)? {
+                                files.push((path, Some(game_type.clone())));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// The Parquet files the query reads, after pruning by month and game type.
+    pub fn files(&self) -> Result<Vec<String>> {
+        Ok(self
+            .partitions()?
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect())
+    }
+
+    /// The filter expression of the query, if it has any filters.
+    pub fn predicate(&self) -> Option<Expr> {
+        let mut filters = Vec::new();
+        if !self.game_types.is_empty() {
+            let types: Vec<String> = self.game_types.iter().map(|t| t.to_string()).collect();
+            filters.push(col("game_type").is_in(lit(Series::new("", types))));
+        }
+        if let Some((min, max)) = self.elo {
+            for column in ["white_player_elo", "black_player_elo"] {
+                filters.push(col(column).gt_eq(lit(min)));
+                filters.push(col(column).lt_eq(lit(max)));
+            }
+        }
+        if let Some(dates) = &self.dates {
+            let date = |d: NaiveDate| lit(d).cast(DataType::Date);
+            filters.push(col("date").gt_eq(date(dates.start)));
+            filters.push(col("date").lt(date(dates.end)));
+        }
+        if let Some(prefix) = &self.eco_prefix {
+            filters.push(col("opening_eco").str().starts_with(lit(prefix.as_str())));
+        }
+        filters.into_iter().reduce(|a, b| a.and(b))
+    }
+
+    /// Scan one file with the query's filter, giving every file the same columns: the
+    /// [`game_schema`] with the enum columns as strings.
+    fn scan(&self, path: &str, game_type: Option<&GameType>) -> PolarsResult<LazyFrame> {
+        let mut lf = LazyFrame::scan_parquet(path, ScanArgsParquet::default())?;
+        if let Some(game_type) = game_type {
+            lf = lf.with_column(lit(game_type.to_string()).alias("game_type"));
+        }
+        let columns: Vec<Expr> = game_schema()
+            .iter()
+            .map(|(name, dtype)| match dtype {
+                DataType::Categorical(_) => col(name).cast(DataType::Utf8),
+                _ => col(name),
+            })
+            .collect();
+        lf = lf.select(columns);
+        Ok(match self.predicate() {
+            Some(predicate) => lf.filter(predicate),
+            None => lf,
+        })
+    }
+
+    /// The matching games as a lazy frame over every file the query reads.
+    pub fn lazy(&self) -> Result<LazyFrame> {
+        let frames = self
+            .partitions()?
+            .iter()
+            .map(|(path, game_type)| self.scan(path, game_type.as_ref()))
+            .collect::<PolarsResult<Vec<LazyFrame>>>()?;
+        if frames.is_empty() {
+            return Ok(DataFrame::from(&game_schema()).lazy());
+        }
+        Ok(concat(frames, false, true)?)
+    }
+
+    /// The matching games, read one file at a time.
+    pub fn games(&self) -> Result<QueryGames> {
+        Ok(QueryGames {
+            query: self.clone(),
+            files: self.partitions()?.into_iter(),
+            games: Vec::new().into_iter(),
+        })
+    }
+}
+
+/// An iterator over the games matching a [`GameQuery`], which only holds one file's
+/// games in memory at a time.
+pub struct QueryGames {
+    query: GameQuery,
+    files: std::vec::IntoIter<(String, Option<GameType>)>,
+    games: std::vec::IntoIter<ChessGame>,
+}
+
+impl Iterator for QueryGames {
+    type Item = Result<ChessGame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(game) = self.games.next() {
+                return Some(Ok(game));
+            }
+            let (path, game_type) = self.files.next()?;
+            let games = self
+                .query
+                .scan(&path, game_type.as_ref())
+                .and_then(LazyFrame::collect)
+                .map_err(anyhow::Error::from)
+                .and_then(|df| dataframe_to_games(&df));
+            match games {
+                Ok(games) => self.games = games.into_iter(),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// The subdirectories of `dir` named `{key}{number}`, e.g. `2016` or `year=2016`, with
+/// their numbers. A missing `dir` has none.
+fn numbered_dirs(dir: &Path, key: &str) -> Result<Vec<(std::path::PathBuf, u32)>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let number = path
+            .file_name()
+            .and_then(|name| name.to_str()?.strip_prefix(key)?.parse().ok());
+        if let (true, Some(number)) = (path.is_dir(), number) {
+            dirs.push((path, number));
+        }
+    }
+    Ok(dirs)
+}
+
+/// The `.parquet` files directly in `dir`.
+fn parquet_files(dir: &Path) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "parquet") {
+            files.push(path.to_string_lossy().into_owned());
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{GameWriter, OutputFormat};
+    use crate::test_game;
+
+    fn game(
+        game_id: &str,
+        game_type: GameType,
+        elo: u32,
+        date: (i32, u32),
+        eco: &str,
+    ) -> ChessGame {
+        let mut game = test_game(game_id);
+        game.game_type = game_type;
+        game.white_player_elo = elo;
+        game.black_player_elo = elo;
+        game.date = NaiveDate::from_ymd_opt(date.0, date.1, 15);
+        game.opening_eco = eco.to_string();
+        game
+    }
+
+    /// Writes the same games for two months in the given layout.
+    fn write_games(root: &str, layout: OutputLayout) {
+        for month in [1, 2] {
+            let mut writer = GameWriter::new(root, 2016, month, layout, OutputFormat::default(), 2);
+            for (i, (game_type, elo, eco)) in [
+                (GameType::Blitz, 1900, "B20"),
+                (GameType::Blitz, 2400, "B20"),
+                (GameType::Bullet, 1900, "B01"),
+                (GameType::Blitz, 2000, "C50"),
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                let id = format!("{}-{}", month, i);
+                writer
+                    .push(game(&id, game_type, elo, (2016, month as u32), eco))
+                    .unwrap();
+            }
+            writer.finish().unwrap();
+        }
+    }
+
+    fn ids(query: &GameQuery) -> Vec<String> {
+        query.games().unwrap().map(|g| g.unwrap().game_id).collect()
+    }
+
+    #[test]
+    fn test_query_flat_layout() {
+        let root = std::env::temp_dir().join(format!("chess_rs_query_flat_{}", std::process::id()));
+        let root = root.to_str().unwrap();
+        write_games(root, OutputLayout::Flat);
+
+        let all = GameQuery::new().root(root);
+        assert_eq!(all.files().unwrap().len(), 4);
+        assert_eq!(ids(&all).len(), 8);
+
+        let query = all
+            .clone()
+            .game_type(GameType::Blitz)
+            .elo_between(1800, 2200)
+            .dates(
+                NaiveDate::from_ymd_opt(2016, 2, 1).unwrap()
+                    ..NaiveDate::from_ymd_opt(2016, 3, 1).unwrap(),
+            )
+            .eco_prefix("B");
+        assert_eq!(query.files().unwrap().len(), 2);
+        assert_eq!(ids(&query), vec!["2-0"]);
+
+        let df = query.lazy().unwrap().collect().unwrap();
+        assert_eq!(df.height(), 1);
+        assert_eq!(df.column("game_type").unwrap().dtype(), &DataType::Utf8);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_query_hive_layout_prunes_partitions() {
+        let root = std::env::temp_dir().join(format!("chess_rs_query_hive_{}", std::process::id()));
+        let root = root.to_str().unwrap();
+        write_games(root, OutputLayout::Hive);
+
+        let query = GameQuery::new()
+            .root(root)
+            .layout(OutputLayout::Hive)
+            .game_type(GameType::Bullet)
+            .dates(
+                NaiveDate::from_ymd_opt(2016, 1, 1).unwrap()
+                    ..NaiveDate::from_ymd_opt(2016, 2, 1).unwrap(),
+            );
+        assert_eq!(query.files().unwrap().len(), 1);
+        assert_eq!(ids(&query), vec!["1-2"]);
+
+        let blitz = GameQuery::new()
+            .root(root)
+            .layout(OutputLayout::Hive)
+            .game_type(GameType::Blitz)
+            .eco_prefix("C5");
+        let df = blitz.lazy().unwrap().collect().unwrap();
+        let ids = df.column("game_id").unwrap().utf8().unwrap();
+        assert_eq!(
+            ids.into_iter().collect::<Vec<_>>(),
+            vec![Some("1-3"), Some("2-3")]
+        );
+        let game_types = df.column("game_type").unwrap().utf8().unwrap();
+        assert_eq!(game_types.get(0), Some("Blitz"));
+
+        let nothing = GameQuery::new()
+            .root("/nonexistent")
+            .lazy()
+            .unwrap()
+            .collect()
+            .unwrap();
+        assert_eq!(nothing.height(), 0);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
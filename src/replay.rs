@@ -0,0 +1,105 @@
+use anyhow::{anyhow, Result};
+use shakmaty::{san::San, CastlingMode, Chess, Move, Position};
+
+use crate::movetext::MoveRecord;
+
+/// A single half-move seen while replaying a game.
+pub struct Ply<'a> {
+    /// 1-based half-move number.
+    pub number: u32,
+    /// The position before the move is played.
+    pub position: &'a Chess,
+    /// The legal move that was played.
+    pub mv: &'a Move,
+    /// The move as recorded in the PGN movetext, including clock and eval.
+    pub record: &'a MoveRecord,
+}
+
+impl Ply<'_> {
+    /// The move in UCI notation, e.g. `e2e4` or `e7e8q`.
+    pub fn uci(&self) -> String {
+        self.mv.to_uci(CastlingMode::Standard).to_string()
+    }
+}
+
+/// Something that wants to observe every ply of a game as it is replayed.
+///
+/// Several visitors can share a single replay pass, so the (comparatively
+/// expensive) SAN resolution is only done once per game.
+pub trait PlyVisitor {
+    /// Called for each half-move, before it is applied to the board.
+    fn visit_ply(&mut self, ply: &Ply<'_>);
+
+    /// Called once with the position after the last move has been played.
+    fn finish(&mut self, _final_position: &Chess) {}
+}
+
+/// A [`PlyVisitor`] that collects the UCI notation of every move.
+#[derive(Debug, Default)]
+pub struct UciRecorder {
+    pub moves: Vec<String>,
+}
+
+impl PlyVisitor for UciRecorder {
+    fn visit_ply(&mut self, ply: &Ply<'_>) {
+        self.moves.push(ply.uci());
+    }
+}
+
+/// Replay a list of moves from the standard starting position.
+///
+/// Each visitor sees every ply in order, then the final position.
+///
+/// # Returns
+///
+/// * The final position, or an error naming the first move that is not legal.
+pub fn replay(moves: &[MoveRecord], visitors: &mut [&mut dyn PlyVisitor]) -> Result<Chess> {
+    let mut position = Chess::default();
+    for (i, record) in moves.iter().enumerate() {
+        let san: San = record
+            .san
+            .parse()
+            .map_err(|e| anyhow!("Invalid SAN '{}' at ply {}: {}", record.san, i + 1, e))?;
+        let mv = san
+            .to_move(&position)
+            .map_err(|e| anyhow!("Illegal move '{}' at ply {}: {}", record.san, i + 1, e))?;
+        let ply = Ply {
+            number: i as u32 + 1,
+            position: &position,
+            mv: &mv,
+            record,
+        };
+        for visitor in visitors.iter_mut() {
+            visitor.visit_ply(&ply);
+        }
+        position.play_unchecked(mv);
+    }
+    for visitor in visitors.iter_mut() {
+        visitor.finish(&position);
+    }
+    Ok(position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movetext::parse_movetext;
+
+    #[test]
+    fn test_replay_visits_every_ply() {
+        let moves = parse_movetext("1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 4. O-O");
+        let mut recorder = UciRecorder::default();
+        let position = replay(&moves, &mut [&mut recorder]).unwrap();
+        assert_eq!(
+            recorder.moves,
+            vec!["e2e4", "e7e5", "g1f3", "b8c6", "f1b5", "a7a6", "e1g1"]
+        );
+        assert_eq!(position.fullmoves().get(), 4);
+    }
+
+    #[test]
+    fn test_replay_rejects_illegal_move() {
+        let moves = parse_movetext("1. e4 e5 2. Ke3");
+        assert!(replay(&moves, &mut []).is_err());
+    }
+}
@@ -0,0 +1,233 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use derive_builder::Builder;
+
+/// A chess variant with its own monthly game archives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Variant {
+    Antichess,
+    Atomic,
+    Chess960,
+    Crazyhouse,
+    Horde,
+    KingOfTheHill,
+    RacingKings,
+    ThreeCheck,
+}
+
+impl Variant {
+    pub const ALL: [Variant; 8] = [
+        Self::Antichess,
+        Self::Atomic,
+        Self::Chess960,
+        Self::Crazyhouse,
+        Self::Horde,
+        Self::KingOfTheHill,
+        Self::RacingKings,
+        Self::ThreeCheck,
+    ];
+}
+
+impl FromStr for Variant {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|variant| variant.to_string().eq_ignore_ascii_case(s))
+            .ok_or(())
+    }
+}
+
+/// The name Lichess uses in the variant's paths, e.g. `kingOfTheHill`.
+impl Display for Variant {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Antichess => write!(f, "antichess"),
+            Self::Atomic => write!(f, "atomic"),
+            Self::Chess960 => write!(f, "chess960"),
+            Self::Crazyhouse => write!(f, "crazyhouse"),
+            Self::Horde => write!(f, "horde"),
+            Self::KingOfTheHill => write!(f, "kingOfTheHill"),
+            Self::RacingKings => write!(f, "racingKings"),
+            Self::ThreeCheck => write!(f, "threeCheck"),
+        }
+    }
+}
+
+/// A dataset published on the Lichess database site.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum DatasetKind {
+    /// Rated standard games, one archive per month.
+    #[default]
+    Standard,
+    /// Rated games of a variant, one archive per month.
+    Variant(Variant),
+    /// The puzzle database, a single CSV file updated in place.
+    Puzzles,
+    /// The position evaluations, a single JSON-lines file updated in place.
+    Evals,
+}
+
+impl DatasetKind {
+    /// Whether the dataset is published as one archive per month.
+    pub fn is_monthly(&self) -> bool {
+        matches!(self, Self::Standard | Self::Variant(_))
+    }
+
+    /// The year and month of the first published archive, for monthly datasets.
+    pub fn first_month(&self) -> Option<(i32, i32)> {
+        match self {
+            Self::Standard => Some((2013, 1)),
+            Self::Variant(_) => Some((2016, 1)),
+            Self::Puzzles | Self::Evals => None,
+        }
+    }
+
+    /// The name of the published file, e.g. `lichess_db_standard_rated_2016-03.pgn.zst`.
+    /// `year` and `month` are ignored for datasets that are not monthly.
+    pub fn file_name(&self, year: i32, month: i32) -> String {
+        match self {
+            Self::Standard | Self::Variant(_) => format!(
+                "lichess_db_{}_rated_{}-{:02}.pgn.zst",
+                self.dir_name(),
+                year,
+                month
+            ),
+            Self::Puzzles => "lichess_db_puzzle.csv.zst".to_string(),
+            Self::Evals => "lichess_db_eval.jsonl.zst".to_string(),
+        }
+    }
+
+    /// The directory of the site holding the monthly archives, if the dataset has one.
+    fn dir_name(&self) -> String {
+        match self {
+            Self::Standard => "standard".to_string(),
+            Self::Variant(variant) => variant.to_string(),
+            Self::Puzzles | Self::Evals => String::new(),
+        }
+    }
+
+    /// Where the dataset is written unless another output root is given, so that
+    /// datasets do not overwrite each other's files: `lichess_data` for standard games
+    /// and e.g. `lichess_data_atomic` for the others.
+    pub fn default_root(&self) -> String {
+        match self {
+            Self::Standard => "lichess_data".to_string(),
+            kind => format!("lichess_data_{}", kind),
+        }
+    }
+}
+
+impl FromStr for DatasetKind {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "standard" => Ok(Self::Standard),
+            "puzzles" => Ok(Self::Puzzles),
+            "evals" => Ok(Self::Evals),
+            _ => Variant::from_str(s).map(Self::Variant),
+        }
+    }
+}
+
+impl Display for DatasetKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Standard => write!(f, "standard"),
+            Self::Variant(variant) => write!(f, "{}", variant),
+            Self::Puzzles => write!(f, "puzzles"),
+            Self::Evals => write!(f, "evals"),
+        }
+    }
+}
+
+/// Where a dataset is downloaded from: the Lichess database site, or a mirror of it
+/// with the same paths.
+#[derive(Debug, Clone, Builder, PartialEq)]
+pub struct DataSource {
+    /// The site root, e.g. `http://localhost:8080`; a trailing slash is ignored.
+    #[builder(default = "\"https://database.lichess.org\".to_string()", setter(into))]
+    pub base_url: String,
+    #[builder(default)]
+    pub kind: DatasetKind,
+}
+
+impl DataSource {
+    pub fn builder() -> DataSourceBuilder {
+        DataSourceBuilder::default()
+    }
+
+    /// The URL of the archive for a given year and month, e.g.
+    /// `https://database.lichess.org/standard/lichess_db_standard_rated_2016-03.pgn.zst`.
+    /// `year` and `month` are ignored for datasets that are not monthly.
+    pub fn url(&self, year: i32, month: i32) -> String {
+        let file_name = self.kind.file_name(year, month);
+        if self.kind.is_monthly() {
+            format!("{}/{}/{}", self.base(), self.kind.dir_name(), file_name)
+        } else {
+            format!("{}/{}", self.base(), file_name)
+        }
+    }
+
+    /// The URL of the checksum list published next to the monthly archives, if the
+    /// dataset has one.
+    pub fn checksums_url(&self) -> Option<String> {
+        self.kind
+            .is_monthly()
+            .then(|| format!("{}/{}/sha256sums.txt", self.base(), self.kind.dir_name()))
+    }
+
+    fn base(&self) -> &str {
+        self.base_url.trim_end_matches('/')
+    }
+}
+
+impl Default for DataSource {
+    fn default() -> Self {
+        Self::builder().build().expect("all fields have defaults")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_source_urls() {
+        let source = DataSource::default();
+        assert_eq!(
+            source.url(2016, 3),
+            "https://database.lichess.org/standard/lichess_db_standard_rated_2016-03.pgn.zst"
+        );
+        assert_eq!(
+            source.checksums_url().unwrap(),
+            "https://database.lichess.org/standard/sha256sums.txt"
+        );
+
+        let mirror = DataSource::builder()
+            .base_url("http://localhost:8080/")
+            .kind(DatasetKind::from_str("kingofthehill").unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(
+            mirror.url(2016, 3),
+            "http://localhost:8080/kingOfTheHill/lichess_db_kingOfTheHill_rated_2016-03.pgn.zst"
+        );
+        assert_eq!(mirror.kind.default_root(), "lichess_data_kingOfTheHill");
+        assert_eq!(source.kind.first_month(), Some((2013, 1)));
+        assert_eq!(mirror.kind.first_month(), Some((2016, 1)));
+
+        let puzzles = DataSource::builder()
+            .kind(DatasetKind::Puzzles)
+            .build()
+            .unwrap();
+        assert_eq!(
+            puzzles.url(2016, 3),
+            "https://database.lichess.org/lichess_db_puzzle.csv.zst"
+        );
+        assert_eq!(puzzles.checksums_url(), None);
+        assert_eq!(puzzles.kind.first_month(), None);
+        assert!(DatasetKind::from_str("bughouse").is_err());
+    }
+}
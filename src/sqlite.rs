@@ -0,0 +1,344 @@
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::analysis::PlayerAnalysis;
+use crate::ChessGame;
+
+/// Schema migrations, applied in order. `PRAGMA user_version` records how many of them
+/// a database has seen, so new fields are added by appending a migration, never by
+/// editing an existing one.
+const MIGRATIONS: &[&str] = &[
+    // 1: games, with their replay summary, engine analysis and moves, and players. The
+    // UNIQUE constraint indexes player names.
+    "CREATE TABLE players (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE
+    );
+    CREATE TABLE games (
+        game_id TEXT PRIMARY KEY,
+        rated INTEGER NOT NULL,
+        url TEXT NOT NULL,
+        game_type TEXT NOT NULL,
+        time_control_base INTEGER NOT NULL,
+        time_control_increment INTEGER NOT NULL,
+        white_player_id INTEGER NOT NULL REFERENCES players(id),
+        white_player_elo INTEGER NOT NULL,
+        black_player_id INTEGER NOT NULL REFERENCES players(id),
+        black_player_elo INTEGER NOT NULL,
+        rating_diff INTEGER NOT NULL,
+        winner TEXT,
+        termination_type TEXT NOT NULL,
+        date TEXT,
+        time TEXT,
+        opening_name TEXT NOT NULL,
+        opening_eco TEXT NOT NULL,
+        ply_count INTEGER NOT NULL,
+        final_fen TEXT,
+        board_outcome TEXT,
+        white_acpl REAL,
+        white_accuracy REAL,
+        white_inaccuracies INTEGER,
+        white_mistakes INTEGER,
+        white_blunders INTEGER,
+        black_acpl REAL,
+        black_accuracy REAL,
+        black_inaccuracies INTEGER,
+        black_mistakes INTEGER,
+        black_blunders INTEGER,
+        moves_san TEXT NOT NULL
+    );
+    CREATE INDEX games_white_player_id ON games(white_player_id);
+    CREATE INDEX games_black_player_id ON games(black_player_id);
+    CREATE INDEX games_date ON games(date);
+    CREATE INDEX games_opening_eco ON games(opening_eco);",
+    // 2: titles and rating changes.
+    "ALTER TABLE games ADD COLUMN white_title TEXT;
+    ALTER TABLE games ADD COLUMN black_title TEXT;
+    ALTER TABLE games ADD COLUMN white_rating_change INTEGER;
+    ALTER TABLE games ADD COLUMN black_rating_change INTEGER;",
+];
+
+/// The schema version a database is at after [`migrate`].
+pub fn latest_schema_version() -> u32 {
+    MIGRATIONS.len() as u32
+}
+
+/// The schema version of a database, 0 for an empty one.
+pub fn schema_version(conn: &Connection) -> Result<u32> {
+    Ok(conn.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+}
+
+/// Bring a database up to the latest schema, applying each missing migration in
+/// its own transaction.
+pub fn migrate(conn: &mut Connection) -> Result<()> {
+    migrate_to(conn, latest_schema_version())
+}
+
+fn migrate_to(conn: &mut Connection, version: u32) -> Result<()> {
+    for (i, migration) in MIGRATIONS
+        .iter()
+        .enumerate()
+        .take(version as usize)
+        .skip(schema_version(conn)? as usize)
+    {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i as u32 + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Streams [`ChessGame`]s into a SQLite database with normalized `games` and `players`
+/// tables, committing a transaction every `batch_size` games.
+///
+/// Games already in the database are replaced, so a month can be loaded again.
+pub struct SqliteWriter {
+    conn: Connection,
+    batch_size: usize,
+    pending: usize,
+}
+
+impl SqliteWriter {
+    /// Open (or create) a database file and migrate it to the latest schema.
+    pub fn open(path: &str, batch_size: usize) -> Result<Self> {
+        Self::new(Connection::open(path)?, batch_size)
+    }
+
+    /// Write to an existing connection, migrating it to the latest schema.
+    pub fn new(mut conn: Connection, batch_size: usize) -> Result<Self> {
+        migrate(&mut conn)?;
+        Ok(Self {
+            conn,
+            batch_size,
+            pending: 0,
+        })
+    }
+
+    fn player_id(&self, name: &str) -> Result<i64> {
+        let existing = self
+            .conn
+            .prepare_cached("SELECT id FROM players WHERE name = ?1")?
+            .query_row([name], |row| row.get(0))
+            .optional()?;
+        if let Some(id) = existing {
+            return Ok(id);
+        }
+        self.conn
+            .prepare_cached("INSERT INTO players (name) VALUES (?1)")?
+            .execute([name])?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Add a game, committing the current batch once it is full.
+    pub fn push(&mut self, game: &ChessGame) -> Result<()> {
+        if self.pending == 0 {
+            self.conn.execute_batch("BEGIN")?;
+        }
+        let white_player_id = self.player_id(&game.white_player_name)?;
+        let black_player_id = self.player_id(&game.black_player_name)?;
+        let analysis = |a: Option<&PlayerAnalysis>| {
+            (
+                a.map(|a| a.acpl),
+                a.map(|a| a.accuracy),
+                a.map(|a| a.inaccuracies),
+                a.map(|a| a.mistakes),
+                a.map(|a| a.blunders),
+            )
+        };
+        let white = analysis(game.white_analysis.as_ref());
+        let black = analysis(game.black_analysis.as_ref());
+        let moves_san: Vec<&str> = game.moves.iter().map(|m| m.san.as_str()).collect();
+
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO games (
+                    game_id, rated, url, game_type, time_control_base, time_control_increment,
+                    white_player_id, white_player_elo, black_player_id, black_player_elo,
+                    rating_diff, winner, termination_type, date, time, opening_name, opening_eco,
+                    ply_count, final_fen, board_outcome,
+                    white_acpl, white_accuracy, white_inaccuracies, white_mistakes, white_blunders,
+                    black_acpl, black_accuracy, black_inaccuracies, black_mistakes, black_blunders,
+                    moves_san, white_title, black_title, white_rating_change, black_rating_change
+                ) VALUES (
+                    ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17,
+                    ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29, ?30, ?31,
+                    ?32, ?33, ?34, ?35
+                )",
+            )?
+            .execute(params![
+                game.game_id,
+                game.rated,
+                game.url,
+                game.game_type.to_string(),
+                game.time_control.base_seconds(),
+                game.time_control.increment_seconds(),
+                white_player_id,
+                game.white_player_elo,
+                black_player_id,
+                game.black_player_elo,
+                game.rating_diff,
+                game.winner.as_ref().map(|w| w.to_string()),
+                game.termination_type.to_string(),
+                game.date.map(|d| d.format("%Y-%m-%d").to_string()),
+                game.time.map(|t| t.format("%H:%M:%S").to_string()),
+                game.opening_name,
+                game.opening_eco,
+                game.ply_count,
+                game.final_fen,
+                game.board_outcome.as_ref().map(|o| o.to_string()),
+                white.0,
+                white.1,
+                white.2,
+                white.3,
+                white.4,
+                black.0,
+                black.1,
+                black.2,
+                black.3,
+                black.4,
+                moves_san.join(" "),
+                game.white_title,
+                game.black_title,
+                game.white_rating_change,
+                game.black_rating_change,
+            ])?;
+
+        self.pending += 1;
+        if self.pending >= self.batch_size {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Commit the games pushed so far, e.g. before recording a checkpoint.
+    pub fn commit(&mut self) -> Result<()> {
+        if self.pending > 0 {
+            self.conn.execute_batch("COMMIT")?;
+            self.pending = 0;
+        }
+        Ok(())
+    }
+
+    /// Commit the last batch.
+    ///
+    /// # Returns
+    ///
+    /// * The number of games in the database, including those loaded by earlier runs.
+    pub fn finish(mut self) -> Result<usize> {
+        self.commit()?;
+        let games: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM games", [], |row| row.get(0))?;
+        Ok(games as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movetext::parse_movetext;
+    use crate::{test_game, Winner};
+    use chrono::NaiveDate;
+
+    fn game(game_id: &str, white: &str, black: &str) -> ChessGame {
+        let mut game = test_game(game_id);
+        game.white_player_name = white.to_string();
+        game.black_player_name = black.to_string();
+        game.winner = Some(Winner::White);
+        game.date = NaiveDate::from_ymd_opt(2016, 3, 1);
+        game
+    }
+
+    #[test]
+    fn test_write_games_and_players() {
+        let mut writer = SqliteWriter::new(Connection::open_in_memory().unwrap(), 2).unwrap();
+        let mut games = vec![
+            game("a", "alice", "bob"),
+            game("b", "bob", "carol"),
+            game("c", "alice", "carol"),
+        ];
+        games[0].moves = parse_movetext("1. e4 c5");
+        for game in &games {
+            writer.push(game).unwrap();
+        }
+        // Loading a game again replaces it.
+        games[0].moves = parse_movetext("1. d4 d5 2. c4");
+        writer.push(&games[0]).unwrap();
+        writer.commit().unwrap();
+        let (count, moves): (u32, String) = writer
+            .conn
+            .query_row(
+                "SELECT COUNT(*), MAX(moves_san) FROM games WHERE game_id = 'a'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((count, moves.as_str()), (1, "d4 d5 c4"));
+        assert_eq!(writer.finish().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_players_are_normalized() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        let mut writer = SqliteWriter::new(conn, 10).unwrap();
+        let mut first = game("a", "alice", "bob");
+        first.moves = parse_movetext("1. e4 c5");
+        writer.push(&first).unwrap();
+        writer.push(&game("b", "bob", "alice")).unwrap();
+        writer.commit().unwrap();
+
+        let conn = &writer.conn;
+        let players: u32 = conn
+            .query_row("SELECT COUNT(*) FROM players", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(players, 2);
+        let (white, date, moves): (String, String, String) = conn
+            .query_row(
+                "SELECT p.name, g.date, g.moves_san FROM games g
+                 JOIN players p ON p.id = g.white_player_id WHERE g.game_id = 'a'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            (white.as_str(), date.as_str(), moves.as_str()),
+            ("alice", "2016-03-01", "e4 c5")
+        );
+    }
+
+    #[test]
+    fn test_migrate_existing_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate_to(&mut conn, 1).unwrap();
+        conn.execute_batch(
+            "INSERT INTO players (id, name) VALUES (1, 'alice'), (2, 'bob');
+             INSERT INTO games (game_id, rated, url, game_type, time_control_base,
+                time_control_increment, white_player_id, white_player_elo, black_player_id,
+                black_player_elo, rating_diff, winner, termination_type, opening_name,
+                opening_eco, ply_count, moves_san)
+             VALUES ('a', 1, 'https://lichess.org/a', 'Blitz', 180, 2, 1, 1600, 2, 1500, 100,
+                'White', 'Normal', 'Sicilian', 'B20', 2, 'e4 c5');",
+        )
+        .unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), 1);
+
+        migrate(&mut conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), latest_schema_version());
+        let (moves, title, change): (String, Option<String>, Option<i32>) = conn
+            .query_row(
+                "SELECT moves_san, white_title, white_rating_change FROM games
+                 WHERE game_id = 'a'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!((moves.as_str(), title, change), ("e4 c5", None, None));
+
+        // Migrating again is a no-op, and the upgraded database takes new games.
+        let mut writer = SqliteWriter::new(conn, 10).unwrap();
+        writer.push(&game("b", "bob", "alice")).unwrap();
+        assert_eq!(writer.finish().unwrap(), 2);
+    }
+}
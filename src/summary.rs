@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use shakmaty::fen::Fen;
+use shakmaty::zobrist::{Zobrist64, ZobristHash};
+use shakmaty::{Chess, EnPassantMode, Position};
+
+use crate::movetext::MoveRecord;
+use crate::replay::{replay, Ply, PlyVisitor, UciRecorder};
+use crate::BoardOutcome;
+
+/// What replaying a game tells us about how it ended.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameSummary {
+    pub ply_count: u32,
+    pub final_fen: String,
+    pub outcome: Option<BoardOutcome>,
+}
+
+/// A [`PlyVisitor`] that tracks repetitions and determines the [`GameSummary`] of a game.
+#[derive(Debug, Default)]
+pub struct OutcomeTracker {
+    ply_count: u32,
+    repetitions: HashMap<u64, u32>,
+    summary: Option<GameSummary>,
+}
+
+impl OutcomeTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The summary, available once the replay has finished.
+    pub fn into_summary(self) -> Option<GameSummary> {
+        self.summary
+    }
+
+    fn record(&mut self, position: &Chess) -> u32 {
+        let key = position.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0;
+        let count = self.repetitions.entry(key).or_default();
+        *count += 1;
+        *count
+    }
+}
+
+impl PlyVisitor for OutcomeTracker {
+    fn visit_ply(&mut self, ply: &Ply<'_>) {
+        self.ply_count = ply.number;
+        self.record(ply.position);
+    }
+
+    fn finish(&mut self, final_position: &Chess) {
+        let repetitions = self.record(final_position);
+        self.summary = Some(GameSummary {
+            ply_count: self.ply_count,
+            final_fen: Fen::from_position(final_position, EnPassantMode::Legal).to_string(),
+            outcome: board_outcome(final_position, repetitions),
+        });
+    }
+}
+
+/// Determine how a game ended on the board, given its final position and how many
+/// times that position has occurred.
+pub fn board_outcome(position: &Chess, repetitions: u32) -> Option<BoardOutcome> {
+    if position.is_checkmate() {
+        Some(BoardOutcome::Checkmate)
+    } else if position.is_stalemate() {
+        Some(BoardOutcome::Stalemate)
+    } else if position.is_insufficient_material() {
+        Some(BoardOutcome::InsufficientMaterial)
+    } else if repetitions >= 3 {
+        Some(BoardOutcome::ThreefoldRepetition)
+    } else if position.halfmoves() >= 100 {
+        Some(BoardOutcome::FiftyMoveRule)
+    } else {
+        None
+    }
+}
+
+/// Replay a list of moves and summarize how the game ended.
+pub fn summarize_moves(moves: &[MoveRecord]) -> Result<GameSummary> {
+    let mut tracker = OutcomeTracker::new();
+    replay(moves, &mut [&mut tracker])?;
+    Ok(tracker.into_summary().expect("replay always calls finish"))
+}
+
+/// Replay a list of moves, fill in the UCI notation of each, and summarize how the game ended.
+/// `visitors` see the plies of the same replay, so they cost no extra pass over the game.
+///
+/// The moves are left untouched if any of them is illegal.
+pub fn replay_and_annotate(
+    moves: &mut [MoveRecord],
+    visitors: &mut [&mut dyn PlyVisitor],
+) -> Result<GameSummary> {
+    let mut tracker = OutcomeTracker::new();
+    let mut uci = UciRecorder::default();
+    let mut all: Vec<&mut dyn PlyVisitor> = vec![&mut tracker, &mut uci];
+    for visitor in visitors.iter_mut() {
+        all.push(&mut **visitor);
+    }
+    replay(moves, &mut all)?;
+    for (record, uci) in moves.iter_mut().zip(uci.moves) {
+        record.uci = Some(uci);
+    }
+    Ok(tracker.into_summary().expect("replay always calls finish"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movetext::parse_movetext;
+
+    #[test]
+    fn test_summarize_checkmate() {
+        let summary = summarize_moves(&parse_movetext("1. f3 e5 2. g4 Qh4# 0-1")).unwrap();
+        assert_eq!(summary.ply_count, 4);
+        assert_eq!(summary.outcome, Some(BoardOutcome::Checkmate));
+        assert_eq!(
+            summary.final_fen,
+            "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3"
+        );
+    }
+
+    #[test]
+    fn test_summarize_threefold_repetition() {
+        let summary = summarize_moves(&parse_movetext(
+            "1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3 Nf6 4. Ng1 Ng8 1/2-1/2",
+        ))
+        .unwrap();
+        assert_eq!(summary.outcome, Some(BoardOutcome::ThreefoldRepetition));
+    }
+
+    #[test]
+    fn test_replay_and_annotate() {
+        let mut moves = parse_movetext("1. e4 e5 2. Ke2");
+        let summary = replay_and_annotate(&mut moves, &mut []).unwrap();
+        assert_eq!(summary.ply_count, 3);
+        assert_eq!(moves[2].uci.as_deref(), Some("e1e2"));
+
+        let mut illegal = parse_movetext("1. e4 e5 2. Ke3");
+        assert!(replay_and_annotate(&mut illegal, &mut []).is_err());
+        assert_eq!(illegal[0].uci, None);
+    }
+
+    #[test]
+    fn test_summarize_unfinished() {
+        let summary = summarize_moves(&parse_movetext("1. e4 e5")).unwrap();
+        assert_eq!(summary.ply_count, 2);
+        assert_eq!(summary.outcome, None);
+    }
+}
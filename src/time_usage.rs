@@ -0,0 +1,193 @@
+use std::time::Duration;
+
+use derive_builder::Builder;
+
+use crate::movetext::MoveRecord;
+use crate::TimeControl;
+
+/// Thresholds used when deriving time-usage statistics from clock annotations.
+#[derive(Debug, Clone, Builder, PartialEq)]
+pub struct TimeUsageConfig {
+    /// A player is in time trouble when their clock is below this fraction of the base time.
+    #[builder(default = "0.1")]
+    pub time_trouble_fraction: f64,
+    /// Moves made with less than this left on the clock are counted as "under ten seconds".
+    #[builder(default = "Duration::from_secs(10)")]
+    pub low_clock_threshold: Duration,
+}
+
+impl TimeUsageConfig {
+    pub fn builder() -> TimeUsageConfigBuilder {
+        TimeUsageConfigBuilder::default()
+    }
+}
+
+impl Default for TimeUsageConfig {
+    fn default() -> Self {
+        Self::builder().build().expect("all fields have defaults")
+    }
+}
+
+/// Time usage of a single move.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MoveTime {
+    /// 1-based half-move number.
+    pub ply: u32,
+    /// Time spent thinking on the move, net of the increment.
+    pub time_spent: Duration,
+    /// Clock time remaining after the move (including the increment).
+    pub remaining: Duration,
+    /// Whether the clock was below the time-trouble threshold after the move.
+    pub time_trouble: bool,
+    /// Whether the clock was below the low-clock threshold after the move.
+    pub low_clock: bool,
+}
+
+/// Derive the time spent on each move that has a clock annotation.
+///
+/// A player's clock starts at the base time, and each move's time spent is the
+/// previous clock reading minus the new one, plus the increment the move earned.
+/// Moves whose clock is missing are skipped, and the next reading is compared
+/// against the last one that was present.
+pub fn move_times(moves: &[MoveRecord], time_control: &TimeControl, config: &TimeUsageConfig) -> Vec<MoveTime> {
+    let base = Duration::from_secs(time_control.base_seconds() as u64);
+    let increment = Duration::from_secs(time_control.increment_seconds() as u64);
+    let time_trouble = base.mul_f64(config.time_trouble_fraction);
+
+    // Last known clock of White and Black.
+    let mut clocks = [base, base];
+    let mut times = Vec::new();
+    for (i, record) in moves.iter().enumerate() {
+        let Some(remaining) = record.clock else {
+            continue;
+        };
+        let side = i % 2;
+        times.push(MoveTime {
+            ply: i as u32 + 1,
+            time_spent: (clocks[side] + increment).saturating_sub(remaining),
+            remaining,
+            time_trouble: remaining < time_trouble,
+            low_clock: remaining < config.low_clock_threshold,
+        });
+        clocks[side] = remaining;
+    }
+    times
+}
+
+/// Per-player summary of clock usage over a game.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PlayerTimeUsage {
+    /// Number of the player's moves with a clock annotation.
+    pub moves: u32,
+    pub total_time_spent: Duration,
+    pub average_time_spent: Duration,
+    pub longest_think: Duration,
+    /// Clock after the player's last move.
+    pub final_clock: Option<Duration>,
+    /// Ply of the player's first move in time trouble, if any.
+    pub time_trouble_ply: Option<u32>,
+    pub moves_in_time_trouble: u32,
+    pub moves_under_low_clock: u32,
+}
+
+impl PlayerTimeUsage {
+    fn from_move_times<'a>(times: impl Iterator<Item = &'a MoveTime>) -> Self {
+        let mut usage = Self::default();
+        for time in times {
+            usage.moves += 1;
+            usage.total_time_spent += time.time_spent;
+            usage.longest_think = usage.longest_think.max(time.time_spent);
+            usage.final_clock = Some(time.remaining);
+            if time.time_trouble {
+                usage.moves_in_time_trouble += 1;
+                usage.time_trouble_ply.get_or_insert(time.ply);
+            }
+            if time.low_clock {
+                usage.moves_under_low_clock += 1;
+            }
+        }
+        if usage.moves > 0 {
+            usage.average_time_spent = usage.total_time_spent / usage.moves;
+        }
+        usage
+    }
+}
+
+/// Summarize the clock usage of both players, or `None` if the game has no clock annotations.
+///
+/// # Returns
+///
+/// * `Some((white, black))`.
+pub fn analyze_time_usage(
+    moves: &[MoveRecord],
+    time_control: &TimeControl,
+    config: &TimeUsageConfig,
+) -> Option<(PlayerTimeUsage, PlayerTimeUsage)> {
+    let times = move_times(moves, time_control, config);
+    if times.is_empty() {
+        return None;
+    }
+    let white = PlayerTimeUsage::from_move_times(times.iter().filter(|t| t.ply % 2 == 1));
+    let black = PlayerTimeUsage::from_move_times(times.iter().filter(|t| t.ply % 2 == 0));
+    Some((white, black))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movetext::parse_movetext;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn test_move_times_with_increment() {
+        // 1+2: every move earns two seconds.
+        let moves = parse_movetext(
+            "1. e4 { [%clk 0:01:02] } 1... e5 { [%clk 0:00:58] } \
+             2. Nf3 { [%clk 0:00:50] } 2... Nc6 { [%clk 0:00:05] }",
+        );
+        let times = move_times(&moves, &TimeControl::new(60, 2), &TimeUsageConfig::default());
+        let spent: Vec<Duration> = times.iter().map(|t| t.time_spent).collect();
+        assert_eq!(spent, vec![secs(0), secs(4), secs(14), secs(55)]);
+        assert!(times[3].time_trouble);
+        assert!(times[3].low_clock);
+        assert!(!times[2].time_trouble);
+    }
+
+    #[test]
+    fn test_move_times_skips_missing_clocks() {
+        let moves = parse_movetext(
+            "1. e4 { [%clk 0:02:55] } 1... e5 2. Nf3 { [%clk 0:02:40] }",
+        );
+        let times = move_times(&moves, &TimeControl::new(180, 0), &TimeUsageConfig::default());
+        assert_eq!(times.len(), 2);
+        assert_eq!(times[1].ply, 3);
+        assert_eq!(times[1].time_spent, secs(15));
+    }
+
+    #[test]
+    fn test_analyze_time_usage() {
+        assert_eq!(
+            analyze_time_usage(&parse_movetext("1. e4 e5"), &TimeControl::new(60, 0), &TimeUsageConfig::default()),
+            None
+        );
+
+        let moves = parse_movetext(
+            "1. e4 { [%clk 0:00:58] } 1... e5 { [%clk 0:00:50] } \
+             2. Nf3 { [%clk 0:00:48] } 2... Nc6 { [%clk 0:00:04] } \
+             3. Bc4 { [%clk 0:00:47] } 3... Nf6 { [%clk 0:00:03] }",
+        );
+        let config = TimeUsageConfig::builder().time_trouble_fraction(0.2).build().unwrap();
+        let (white, black) = analyze_time_usage(&moves, &TimeControl::new(60, 0), &config).unwrap();
+        assert_eq!(white.moves, 3);
+        assert_eq!(white.total_time_spent, secs(13));
+        assert_eq!(white.longest_think, secs(10));
+        assert_eq!(white.moves_in_time_trouble, 0);
+        assert_eq!(black.final_clock, Some(secs(3)));
+        assert_eq!(black.average_time_spent, secs(19));
+        assert_eq!(black.time_trouble_ply, Some(4));
+        assert_eq!(black.moves_under_low_clock, 2);
+    }
+}